@@ -0,0 +1,28 @@
+use embedded_hal_1::i2c::I2c as BlockingI2c;
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+
+/// Drives a blocking embedded-hal 1.0 bus through the async `I2c` trait, every transfer
+/// runs to completion on the first poll of its future
+pub struct Blocking<T>(pub T);
+
+impl<T: BlockingI2c> ErrorType for Blocking<T> {
+    type Error = T::Error;
+}
+
+impl<T: BlockingI2c> I2c for Blocking<T> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.read(address, read)
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(address, write)
+    }
+
+    async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        self.0.write_read(address, write, read)
+    }
+
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        self.0.transaction(address, operations)
+    }
+}
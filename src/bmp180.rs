@@ -0,0 +1,466 @@
+use embassy_time::{Duration, Instant};
+use embedded_hal_async::i2c::I2c;
+use libm::powf;
+
+use crate::constants::*;
+use crate::gy87::{BusError, Gy87Error};
+use crate::registers::{write_register, Bmp180Control, FieldValue, RegisterValue, BMP180_MEASUREMENT, BMP180_OVERSAMPLING};
+
+/// Standard pressure at sea level in Pa
+const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+/// Time constant of the altitude low pass in seconds
+const ALTITUDE_TIME_CONSTANT: f32 = 0.5;
+/// Filtered altitude samples averaged into the zero reference
+const REFERENCE_SAMPLES: u16 = 16;
+/// Temperature conversion time
+const TEMP_CONVERSION_TIME: Duration = Duration::from_micros(4500);
+
+/// Pressure oversampling, more samples lower the noise at the cost of conversion time
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum BaroOversampling {
+    X1,
+    X2,
+    X4,
+    X8,
+}
+
+impl BaroOversampling {
+    /// Maximum pressure conversion time from the datasheet
+    fn conversion_time(&self) -> Duration {
+        match self {
+            BaroOversampling::X1 => Duration::from_micros(4500),
+            BaroOversampling::X2 => Duration::from_micros(7500),
+            BaroOversampling::X4 => Duration::from_micros(13500),
+            BaroOversampling::X8 => Duration::from_micros(25500),
+        }
+    }
+}
+
+/// Factory calibration coefficients from the BMP180 EEPROM
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Bmp180Calibration {
+    pub ac1: i16,
+    pub ac2: i16,
+    pub ac3: i16,
+    pub ac4: u16,
+    pub ac5: u16,
+    pub ac6: u16,
+    pub b1: i16,
+    pub b2: i16,
+    pub mb: i16,
+    pub mc: i16,
+    pub md: i16,
+}
+
+impl Bmp180Calibration {
+    /// Decodes the 11 big endian words starting at `BMP180_CALIBRATION`
+    pub fn from_bytes(rx_buffer: &[u8; BMP180_CALIBRATION_LENGTH]) -> Self {
+        let i = |at: usize| i16::from_be_bytes(rx_buffer[at..at + 2].try_into().unwrap());
+        let u = |at: usize| u16::from_be_bytes(rx_buffer[at..at + 2].try_into().unwrap());
+
+        Self {
+            ac1: i(0),
+            ac2: i(2),
+            ac3: i(4),
+            ac4: u(6),
+            ac5: u(8),
+            ac6: u(10),
+            b1: i(12),
+            b2: i(14),
+            mb: i(16),
+            mc: i(18),
+            md: i(20),
+        }
+    }
+
+    /// Returns B5, which the pressure compensation needs, and the temperature in °C
+    pub fn temperature(&self, ut: i32) -> (i32, f32) {
+        let x1 = ((ut - self.ac6 as i32) * self.ac5 as i32) >> 15;
+        let x2 = ((self.mc as i32) << 11) / (x1 + self.md as i32);
+        let b5 = x1 + x2;
+        (b5, ((b5 + 8) >> 4) as f32 / 10.0)
+    }
+
+    /// Compensated pressure in Pa, integer math as given in the datasheet
+    pub fn pressure(&self, up: i32, b5: i32, oss: u8) -> i32 {
+        let b6 = b5 - 4000;
+        let x1 = (self.b2 as i32 * ((b6 * b6) >> 12)) >> 11;
+        let x2 = (self.ac2 as i32 * b6) >> 11;
+        let x3 = x1 + x2;
+        let b3 = ((((self.ac1 as i32) * 4 + x3) << oss) + 2) / 4;
+
+        let x1 = (self.ac3 as i32 * b6) >> 13;
+        let x2 = (self.b1 as i32 * ((b6 * b6) >> 12)) >> 16;
+        let x3 = (x1 + x2 + 2) >> 2;
+        let b4 = (self.ac4 as u32 * (x3 + 32768) as u32) >> 15;
+        let b7 = (up as u32).wrapping_sub(b3 as u32).wrapping_mul(50000 >> oss);
+
+        let p = if b7 < 0x8000_0000 {
+            (b7 * 2 / b4) as i32
+        } else {
+            (b7 / b4 * 2) as i32
+        };
+
+        let x1 = (p >> 8) * (p >> 8);
+        let x1 = (x1 * 3038) >> 16;
+        let x2 = (-7357 * p) >> 16;
+        p + ((x1 + x2 + 3791) >> 4)
+    }
+}
+
+/// Conversion the BMP180 is currently running
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Conversion {
+    Temperature,
+    Pressure,
+}
+
+/// BMP180 readings and the relative altitude derived from them, conversions alternate
+/// between temperature and pressure so the sensor is never waited on
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Barometer {
+    calibration: Bmp180Calibration,
+    oversampling: BaroOversampling,
+    pending: Option<(Conversion, Instant)>,
+    b5: Option<i32>,
+    temp: Option<f32>,
+    pressure: Option<f32>,
+    last_pressure: Option<Instant>,
+    filtered: Option<f32>,
+    reference: f32,
+    reference_samples: u16,
+}
+
+impl Barometer {
+    pub fn new(calibration: Bmp180Calibration, oversampling: BaroOversampling) -> Self {
+        Self {
+            calibration,
+            oversampling,
+            pending: None,
+            b5: None,
+            temp: None,
+            pressure: None,
+            last_pressure: None,
+            filtered: None,
+            reference: 0.0,
+            reference_samples: 0,
+        }
+    }
+
+    /// Whether the last started conversion still runs at `now`
+    pub fn busy(&self, now: Instant) -> bool {
+        matches!(self.pending, Some((_, ready_at)) if now < ready_at)
+    }
+
+    /// Last started conversion
+    pub fn pending(&self) -> Option<Conversion> {
+        self.pending.map(|(conversion, _)| conversion)
+    }
+
+    /// Records a started conversion and returns the control register value that starts it
+    pub fn start(&mut self, conversion: Conversion, now: Instant) -> RegisterValue<Bmp180Control> {
+        let control = RegisterValue::new().with(BMP180_MEASUREMENT, conversion);
+        match conversion {
+            Conversion::Temperature => {
+                self.pending = Some((conversion, now + TEMP_CONVERSION_TIME));
+                control
+            }
+            Conversion::Pressure => {
+                self.pending = Some((conversion, now + self.oversampling.conversion_time()));
+                control.with(BMP180_OVERSAMPLING, self.oversampling)
+            }
+        }
+    }
+
+    /// Forgets the running conversion, e.g. after the sensor lost power
+    pub fn abort(&mut self) {
+        self.pending = None;
+    }
+
+    pub fn add_temperature(&mut self, ut: i32) {
+        let (b5, temp) = self.calibration.temperature(ut);
+        self.b5 = Some(b5);
+        self.temp = Some(temp);
+    }
+
+    /// `rx_buffer` holds MSB, LSB and XLSB of the pressure output
+    pub fn add_pressure(&mut self, rx_buffer: &[u8; 3], now: Instant) {
+        let b5 = match self.b5 {
+            Some(b5) => b5,
+            None => return,
+        };
+
+        let oss = self.oversampling.bits();
+        let up = ((rx_buffer[0] as i32) << 16 | (rx_buffer[1] as i32) << 8 | rx_buffer[2] as i32) >> (8 - oss);
+        let pressure = self.calibration.pressure(up, b5, oss) as f32;
+        self.pressure = Some(pressure);
+
+        let altitude = 44330.0 * (1.0 - powf(pressure / SEA_LEVEL_PRESSURE, 1.0 / 5.255));
+        let filtered = match (self.filtered, self.last_pressure) {
+            (Some(filtered), Some(last)) => {
+                let dt = (now - last).as_micros() as f32 / 1_000_000.0;
+                filtered + (altitude - filtered) * dt / (ALTITUDE_TIME_CONSTANT + dt)
+            }
+            _ => altitude,
+        };
+        self.filtered = Some(filtered);
+        self.last_pressure = Some(now);
+
+        if self.reference_samples < REFERENCE_SAMPLES {
+            self.reference_samples += 1;
+            self.reference += (filtered - self.reference) / self.reference_samples as f32;
+        }
+    }
+
+    /// Die temperature in °C
+    pub fn temp(&self) -> Option<f32> {
+        self.temp
+    }
+
+    /// Compensated pressure in Pa
+    pub fn pressure(&self) -> Option<f32> {
+        self.pressure
+    }
+
+    /// Filtered altitude in meters relative to where the reference was taken
+    pub fn altitude(&self) -> Option<f32> {
+        if self.reference_samples < REFERENCE_SAMPLES {
+            return None;
+        }
+        self.filtered.map(|filtered| filtered - self.reference)
+    }
+
+    /// Takes a new zero reference from the next samples
+    pub fn zero(&mut self) {
+        self.reference = 0.0;
+        self.reference_samples = 0;
+    }
+}
+
+/// BMP180 on its own handle of a shared bus, so its transfers queue up with the motion
+/// sensors' instead of going through their handle
+pub struct Bmp180<I> {
+    i2c: I,
+    oversampling: BaroOversampling,
+    baro: Option<Barometer>,
+}
+
+impl<I: I2c> Bmp180<I> {
+    pub fn new(i2c: I, oversampling: BaroOversampling) -> Self {
+        Self {
+            i2c,
+            oversampling,
+            baro: None,
+        }
+    }
+
+    /// Checks the chip id and reads the calibration EEPROM
+    pub async fn start(&mut self) -> Result<(), Gy87Error> {
+        let mut chip_id = [0u8; 1];
+        self.get_bytes(BMP180_CHIP_ID_ADDR, &mut chip_id).await.map_err(|e| Gy87Error::BaroInit(e))?;
+        if chip_id[0] != BMP180_CHIP_ID {
+            return Err(Gy87Error::UnknownBMPDeviceAddr(chip_id[0]));
+        }
+
+        let mut rx_buffer = [0u8; BMP180_CALIBRATION_LENGTH];
+        self.get_bytes(BMP180_CALIBRATION, &mut rx_buffer).await.map_err(|e| Gy87Error::BaroInit(e))?;
+        self.baro = Some(Barometer::new(Bmp180Calibration::from_bytes(&rx_buffer), self.oversampling));
+        Ok(())
+    }
+
+    /// Collects a finished conversion and starts the next one, never waits on the sensor
+    pub async fn poll(&mut self) -> Result<(), BusError> {
+        let mut baro = match self.baro {
+            Some(baro) => baro,
+            None => return Ok(()),
+        };
+
+        let now = Instant::now();
+        if baro.busy(now) {
+            return Ok(());
+        }
+
+        let next = match baro.pending() {
+            Some(Conversion::Temperature) => {
+                let mut rx_buffer = [0u8; 2];
+                self.get_bytes(BMP180_OUTPUT, &mut rx_buffer).await?;
+                baro.add_temperature(u16::from_be_bytes(rx_buffer) as i32);
+                Conversion::Pressure
+            }
+            Some(Conversion::Pressure) => {
+                let mut rx_buffer = [0u8; 3];
+                self.get_bytes(BMP180_OUTPUT, &mut rx_buffer).await?;
+                baro.add_pressure(&rx_buffer, now);
+                Conversion::Temperature
+            }
+            None => Conversion::Temperature,
+        };
+
+        let control = baro.start(next, Instant::now());
+        write_register(&mut self.i2c, BMP180_ADDR, control).await?;
+        self.baro = Some(baro);
+        Ok(())
+    }
+
+    /// Forgets the running conversion, e.g. after the sensor lost power
+    pub fn abort(&mut self) {
+        if let Some(baro) = &mut self.baro {
+            baro.abort();
+        }
+    }
+
+    /// Latest readings, `None` before `start`
+    pub fn barometer(&self) -> Option<&Barometer> {
+        self.baro.as_ref()
+    }
+
+    /// Takes a new zero reference for the altitude from the next samples
+    pub fn zero(&mut self) {
+        if let Some(baro) = &mut self.baro {
+            baro.zero();
+        }
+    }
+
+    async fn get_bytes(&mut self, addr: u8, buffer: &mut [u8]) -> Result<(), BusError> {
+        self.i2c.write_read(BMP180_ADDR, &[addr], buffer).await.map_err(|_| BusError::BusReadWrite)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Coefficients of the worked example in the datasheet
+    fn datasheet() -> Bmp180Calibration {
+        Bmp180Calibration {
+            ac1: 408,
+            ac2: -72,
+            ac3: -14383,
+            ac4: 32741,
+            ac5: 32757,
+            ac6: 23153,
+            b1: 6190,
+            b2: 4,
+            mb: -32768,
+            mc: -8711,
+            md: 2868,
+        }
+    }
+
+    /// Pressure output registers for `up` at 1x oversampling
+    fn up_bytes(up: i32) -> [u8; 3] {
+        [(up >> 8) as u8, up as u8, 0]
+    }
+
+    /// Barometer with a settled zero reference at the datasheet pressure
+    fn zeroed(now: &mut Instant) -> Barometer {
+        let mut baro = Barometer::new(datasheet(), BaroOversampling::X1);
+        baro.add_temperature(27898);
+        for _ in 0..REFERENCE_SAMPLES {
+            *now += Duration::from_millis(100);
+            baro.add_pressure(&up_bytes(23843), *now);
+        }
+        baro
+    }
+
+    #[test]
+    fn matches_the_datasheet_example() {
+        let calibration = datasheet();
+        let (b5, temp) = calibration.temperature(27898);
+        assert_eq!(temp, 15.0);
+        assert_eq!(calibration.pressure(23843, b5, 0), 69964);
+
+        let mut baro = Barometer::new(calibration, BaroOversampling::X1);
+        baro.add_temperature(27898);
+        baro.add_pressure(&up_bytes(23843), Instant::from_millis(0));
+        assert_eq!(baro.temp(), Some(15.0));
+        assert_eq!(baro.pressure(), Some(69964.0));
+    }
+
+    #[test]
+    fn decodes_calibration_words() {
+        let mut rx_buffer = [0u8; BMP180_CALIBRATION_LENGTH];
+        rx_buffer[..4].copy_from_slice(&[0x01, 0x98, 0xFF, 0xB8]);
+        let calibration = Bmp180Calibration::from_bytes(&rx_buffer);
+        assert_eq!(calibration.ac1, 408);
+        assert_eq!(calibration.ac2, -72);
+    }
+
+    #[test]
+    fn pressure_needs_a_temperature_first() {
+        let mut baro = Barometer::new(datasheet(), BaroOversampling::X1);
+        baro.add_pressure(&up_bytes(23843), Instant::from_millis(0));
+        assert_eq!(baro.pressure(), None);
+    }
+
+    #[test]
+    fn altitude_waits_for_the_reference() {
+        let mut now = Instant::from_millis(0);
+        let mut baro = Barometer::new(datasheet(), BaroOversampling::X1);
+        baro.add_temperature(27898);
+        for _ in 1..REFERENCE_SAMPLES {
+            now += Duration::from_millis(100);
+            baro.add_pressure(&up_bytes(23843), now);
+            assert_eq!(baro.altitude(), None);
+        }
+
+        now += Duration::from_millis(100);
+        baro.add_pressure(&up_bytes(23843), now);
+        assert!(baro.altitude().unwrap().abs() < 1e-3);
+    }
+
+    #[test]
+    fn altitude_follows_a_step_with_the_time_constant() {
+        let mut now = Instant::from_millis(0);
+        let mut settled = zeroed(&mut now);
+        let mut stepped = settled;
+
+        // lower pressure, higher up
+        let mut at = now;
+        for _ in 0..100 {
+            at += Duration::from_millis(500);
+            settled.add_pressure(&up_bytes(23700), at);
+        }
+        let height = settled.altitude().unwrap();
+        assert!(height > 10.0);
+
+        // one sample a time constant later covers half the step
+        stepped.add_pressure(&up_bytes(23700), now + Duration::from_millis(500));
+        let altitude = stepped.altitude().unwrap();
+        assert!((altitude - height / 2.0).abs() < 0.05, "{} vs {}", altitude, height);
+    }
+
+    #[test]
+    fn zero_takes_a_new_reference() {
+        let mut now = Instant::from_millis(0);
+        let mut baro = zeroed(&mut now);
+        for _ in 0..100 {
+            now += Duration::from_millis(500);
+            baro.add_pressure(&up_bytes(23700), now);
+        }
+        assert!(baro.altitude().unwrap() > 10.0);
+
+        baro.zero();
+        assert_eq!(baro.altitude(), None);
+        for _ in 0..REFERENCE_SAMPLES {
+            now += Duration::from_millis(500);
+            baro.add_pressure(&up_bytes(23700), now);
+        }
+        assert!(baro.altitude().unwrap().abs() < 1e-3);
+    }
+
+    #[test]
+    fn start_selects_the_conversion() {
+        let now = Instant::from_millis(0);
+        let mut baro = Barometer::new(datasheet(), BaroOversampling::X8);
+        assert_eq!(baro.start(Conversion::Temperature, now).bits(), 0x2E);
+        assert!(baro.busy(now + Duration::from_millis(4)));
+        assert!(!baro.busy(now + Duration::from_millis(5)));
+
+        assert_eq!(baro.start(Conversion::Pressure, now).bits(), 0xF4);
+        assert_eq!(baro.pending(), Some(Conversion::Pressure));
+        assert!(baro.busy(now + Duration::from_millis(25)));
+        assert!(!baro.busy(now + Duration::from_millis(26)));
+    }
+}
@@ -0,0 +1,322 @@
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::{Poll, Waker};
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex as BlockingMutex;
+use embassy_sync::mutex::Mutex;
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+
+use crate::recovery::BusRecovery;
+
+/// Slots of the handles waiting for the bus in arrival order, the first one owns it. A
+/// handle has at most one transfer in flight and leaves the queue as soon as that transfer
+/// is done or cancelled, so `N` handles never queue more than `N` entries
+struct Queue<const N: usize> {
+    waiting: [usize; N],
+    len: usize,
+    wakers: [Option<Waker>; N],
+    /// Slots held by a handle
+    taken: [bool; N],
+}
+
+impl<const N: usize> Queue<N> {
+    fn new() -> Self {
+        Self {
+            waiting: [0; N],
+            len: 0,
+            wakers: core::array::from_fn(|_| None),
+            taken: [false; N],
+        }
+    }
+
+    fn push(&mut self, slot: usize) {
+        debug_assert!(!self.waiting[..self.len].contains(&slot), "slot queued twice");
+        self.waiting[self.len] = slot;
+        self.len += 1;
+    }
+
+    fn is_first(&self, slot: usize) -> bool {
+        self.len > 0 && self.waiting[0] == slot
+    }
+
+    /// Takes `slot` out of the queue and wakes the next owner if it held the bus
+    fn remove(&mut self, slot: usize) {
+        self.wakers[slot] = None;
+        let at = match self.waiting[..self.len].iter().position(|waiting| *waiting == slot) {
+            Some(at) => at,
+            None => return,
+        };
+        self.waiting.copy_within(at + 1..self.len, at);
+        self.len -= 1;
+
+        if at == 0 && self.len > 0 {
+            if let Some(waker) = self.wakers[self.waiting[0]].take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// I2C bus shared between drivers, transfers are served strictly in the order they were
+/// started so a device issuing back to back transfers can't starve the others
+pub struct SharedBus<M: RawMutex, I, const N: usize> {
+    bus: Mutex<M, I>,
+    queue: BlockingMutex<M, RefCell<Queue<N>>>,
+}
+
+impl<M: RawMutex, I: I2c, const N: usize> SharedBus<M, I, N> {
+    pub fn new(i2c: I) -> Self {
+        Self {
+            bus: Mutex::new(i2c),
+            queue: BlockingMutex::new(RefCell::new(Queue::new())),
+        }
+    }
+
+    /// Handle for one driver, `None` while `N` handles are out
+    pub fn device(&self) -> Option<I2cDevice<'_, M, I, N>> {
+        self.queue.lock(|queue| {
+            let mut queue = queue.borrow_mut();
+            let slot = queue.taken.iter().position(|taken| !taken)?;
+            queue.taken[slot] = true;
+            Some(I2cDevice {
+                shared: self,
+                slot,
+            })
+        })
+    }
+
+    /// Waits until every transfer started earlier is done
+    async fn turn(&self, slot: usize) -> Turn<'_, M, I, N> {
+        self.queue.lock(|queue| queue.borrow_mut().push(slot));
+        let turn = Turn {
+            shared: self,
+            slot,
+        };
+
+        poll_fn(|cx| {
+            self.queue.lock(|queue| {
+                let mut queue = queue.borrow_mut();
+                if queue.is_first(slot) {
+                    Poll::Ready(())
+                } else {
+                    queue.wakers[slot] = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            })
+        })
+        .await;
+
+        turn
+    }
+}
+
+/// Place in the queue, dropping it leaves the queue and hands the bus on if it was ours,
+/// which also covers a transfer cancelled before its turn
+struct Turn<'a, M: RawMutex, I, const N: usize> {
+    shared: &'a SharedBus<M, I, N>,
+    slot: usize,
+}
+
+impl<'a, M: RawMutex, I, const N: usize> Drop for Turn<'a, M, I, N> {
+    fn drop(&mut self) {
+        self.shared.queue.lock(|queue| queue.borrow_mut().remove(self.slot));
+    }
+}
+
+/// One driver's view of a `SharedBus`
+pub struct I2cDevice<'a, M: RawMutex, I, const N: usize> {
+    shared: &'a SharedBus<M, I, N>,
+    slot: usize,
+}
+
+impl<'a, M: RawMutex, I, const N: usize> Drop for I2cDevice<'a, M, I, N> {
+    fn drop(&mut self) {
+        self.shared.queue.lock(|queue| queue.borrow_mut().taken[self.slot] = false);
+    }
+}
+
+impl<'a, M: RawMutex, I: I2c, const N: usize> ErrorType for I2cDevice<'a, M, I, N> {
+    type Error = I::Error;
+}
+
+impl<'a, M: RawMutex, I: I2c, const N: usize> I2c for I2cDevice<'a, M, I, N> {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        let _turn = self.shared.turn(self.slot).await;
+        let mut bus = self.shared.bus.lock().await;
+        bus.read(address, read).await
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        let _turn = self.shared.turn(self.slot).await;
+        let mut bus = self.shared.bus.lock().await;
+        bus.write(address, write).await
+    }
+
+    async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        let _turn = self.shared.turn(self.slot).await;
+        let mut bus = self.shared.bus.lock().await;
+        bus.write_read(address, write, read).await
+    }
+
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        let _turn = self.shared.turn(self.slot).await;
+        let mut bus = self.shared.bus.lock().await;
+        bus.transaction(address, operations).await
+    }
+}
+
+// recovery can't wait for the bus, it is skipped while a transfer is in flight and the
+// caller tries again later
+impl<'a, M: RawMutex, I: I2c + BusRecovery, const N: usize> BusRecovery for I2cDevice<'a, M, I, N> {
+    fn unstick(&mut self) -> bool {
+        match self.shared.bus.try_lock() {
+            Ok(mut bus) => bus.unstick(),
+            Err(_) => false,
+        }
+    }
+
+    fn recreate(&mut self) -> bool {
+        match self.shared.bus.try_lock() {
+            Ok(mut bus) => bus.recreate(),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::Future;
+    use core::pin::{pin, Pin};
+    use core::task::Context;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use futures::executor::block_on;
+    use futures::task::{noop_waker_ref, waker, ArcWake};
+
+    use super::*;
+    use crate::mock::MockI2c;
+
+    type Bus = SharedBus<NoopRawMutex, MockI2c, 3>;
+
+    struct Woken(AtomicBool);
+
+    impl ArcWake for Woken {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn turns_follow_arrival_order() {
+        let bus = Bus::new(MockI2c::new());
+        let mut first = pin!(bus.turn(0));
+        let mut second = pin!(bus.turn(1));
+        let mut third = pin!(bus.turn(2));
+
+        let first = match poll(first.as_mut()) {
+            Poll::Ready(turn) => turn,
+            Poll::Pending => panic!("empty bus not granted"),
+        };
+
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let second_waker = waker(woken.clone());
+        assert!(second.as_mut().poll(&mut Context::from_waker(&second_waker)).is_pending());
+        assert!(poll(third.as_mut()).is_pending());
+
+        drop(first);
+        assert!(woken.0.load(Ordering::SeqCst));
+        assert!(poll(third.as_mut()).is_pending());
+        let second = match poll(second.as_mut()) {
+            Poll::Ready(turn) => turn,
+            Poll::Pending => panic!("next in line not granted"),
+        };
+
+        drop(second);
+        assert!(poll(third.as_mut()).is_ready());
+    }
+
+    #[test]
+    fn cancelled_turns_leave_the_queue() {
+        let bus = Bus::new(MockI2c::new());
+        let mut owner = pin!(bus.turn(0));
+        let owner = match poll(owner.as_mut()) {
+            Poll::Ready(turn) => turn,
+            Poll::Pending => panic!("empty bus not granted"),
+        };
+
+        // far more cancellations than slots, none of them may hand out the bus
+        for _ in 0..10 {
+            let mut cancelled = Box::pin(bus.turn(1));
+            assert!(poll(cancelled.as_mut()).is_pending());
+            drop(cancelled);
+
+            let mut waiting = Box::pin(bus.turn(2));
+            assert!(poll(waiting.as_mut()).is_pending());
+        }
+
+        let mut waiting = pin!(bus.turn(2));
+        assert!(poll(waiting.as_mut()).is_pending());
+        let mut late = pin!(bus.turn(1));
+        assert!(poll(late.as_mut()).is_pending());
+
+        drop(owner);
+        assert!(poll(late.as_mut()).is_pending());
+        assert!(poll(waiting.as_mut()).is_ready());
+    }
+
+    #[test]
+    fn hands_out_at_most_n_devices() {
+        let bus = Bus::new(MockI2c::new());
+        let first = bus.device().unwrap();
+        let second = bus.device().unwrap();
+        let third = bus.device().unwrap();
+        assert!(bus.device().is_none());
+
+        let slot = second.slot;
+        drop(second);
+        let again = bus.device().unwrap();
+        assert_eq!(again.slot, slot);
+        assert_ne!(first.slot, third.slot);
+    }
+
+    #[test]
+    fn devices_share_the_bus() {
+        let mut i2c = MockI2c::new();
+        i2c.device(0x68).device(0x77);
+        let bus = Bus::new(i2c);
+        let mut imu = bus.device().unwrap();
+        let mut baro = bus.device().unwrap();
+
+        block_on(async {
+            imu.write(0x68, &[0x6B, 0x00]).await.unwrap();
+            baro.write(0x77, &[0xF4, 0x2E]).await.unwrap();
+            imu.write(0x68, &[0x1A, 0x03]).await.unwrap();
+        });
+
+        let i2c = bus.bus.try_lock().unwrap();
+        assert_eq!(i2c.writes(), &[(0x68, vec![0x6B, 0x00]), (0x77, vec![0xF4, 0x2E]), (0x68, vec![0x1A, 0x03])]);
+    }
+
+    #[test]
+    fn recovery_is_skipped_while_the_bus_is_in_use() {
+        let bus = Bus::new(MockI2c::new());
+        let mut imu = bus.device().unwrap();
+
+        let in_use = bus.bus.try_lock().unwrap();
+        assert!(!imu.unstick());
+        assert!(!imu.recreate());
+        drop(in_use);
+
+        assert!(imu.unstick());
+        assert!(imu.recreate());
+        let i2c = bus.bus.try_lock().unwrap();
+        assert_eq!((i2c.unsticks, i2c.recreates), (1, 1));
+    }
+}
@@ -0,0 +1,299 @@
+use fusion_rs::nalgebra::{Matrix3, Matrix4, Matrix4x3, Vector3, Vector4};
+use fusion_rs::Vec3;
+use libm::sqrtf;
+
+use crate::gy87::AccelGyro;
+
+/// Largest gyro standard deviation in °/s on any axis that still counts as still
+const GYRO_STILL_STD: f32 = 1.0;
+/// Largest accel standard deviation in g on any axis that still counts as still
+const ACCEL_STILL_STD: f32 = 0.02;
+
+/// Running mean and variance per axis, Welford's method
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Stats {
+    mean: Vec3,
+    m2: Vec3,
+}
+
+impl Stats {
+    fn new() -> Self {
+        Self {
+            mean: Vector3::zeros(),
+            m2: Vector3::zeros(),
+        }
+    }
+
+    fn add(&mut self, n: u16, value: &Vec3) {
+        let delta = value - self.mean;
+        self.mean += delta / n as f32;
+        self.m2 += delta.component_mul(&(value - self.mean));
+    }
+
+    fn max_std(&self, n: u16) -> f32 {
+        sqrtf(self.m2.max() / n as f32)
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum GyroCalibrationStep {
+    Collecting,
+    /// The tracker moved during the window, collection starts over
+    Moved,
+    /// Zero rate offset in °/s
+    Done(Vec3),
+}
+
+/// Averages the gyro over a window of samples taken while the tracker is still
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GyroCalibrator {
+    window: u16,
+    samples: u16,
+    gyro: Stats,
+    accel: Stats,
+}
+
+impl GyroCalibrator {
+    pub fn new(window: u16) -> Self {
+        Self {
+            window: window.max(2),
+            samples: 0,
+            gyro: Stats::new(),
+            accel: Stats::new(),
+        }
+    }
+
+    pub fn add(&mut self, accel_gyro: &AccelGyro) -> GyroCalibrationStep {
+        self.samples += 1;
+        self.gyro.add(self.samples, &accel_gyro.gyro);
+        self.accel.add(self.samples, &accel_gyro.accel);
+        if self.samples < self.window {
+            return GyroCalibrationStep::Collecting;
+        }
+
+        let still = self.gyro.max_std(self.samples) <= GYRO_STILL_STD && self.accel.max_std(self.samples) <= ACCEL_STILL_STD;
+        let bias = self.gyro.mean;
+        *self = Self::new(self.window);
+        if still {
+            GyroCalibrationStep::Done(bias)
+        } else {
+            GyroCalibrationStep::Moved
+        }
+    }
+}
+
+/// Cosine of the largest angle between a face's average and the axis it should point along
+const FACE_ALIGNMENT: f32 = 0.9;
+
+/// Resting position of the six position accelerometer calibration, named after the sensor
+/// axis pointing up
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Face {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    ZUp,
+    ZDown,
+}
+
+impl Face {
+    pub const ALL: [Face; 6] = [Face::XUp, Face::XDown, Face::YUp, Face::YDown, Face::ZUp, Face::ZDown];
+
+    fn index(&self) -> usize {
+        match self {
+            Face::XUp => 0,
+            Face::XDown => 1,
+            Face::YUp => 2,
+            Face::YDown => 3,
+            Face::ZUp => 4,
+            Face::ZDown => 5,
+        }
+    }
+
+    /// Reading of an ideal accelerometer resting on this face, in g
+    fn gravity(&self) -> Vec3 {
+        match self {
+            Face::XUp => Vector3::new(1.0, 0.0, 0.0),
+            Face::XDown => Vector3::new(-1.0, 0.0, 0.0),
+            Face::YUp => Vector3::new(0.0, 1.0, 0.0),
+            Face::YDown => Vector3::new(0.0, -1.0, 0.0),
+            Face::ZUp => Vector3::new(0.0, 0.0, 1.0),
+            Face::ZDown => Vector3::new(0.0, 0.0, -1.0),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum FaceError {
+    /// The tracker moved while the face was collected
+    Moved,
+    /// The average doesn't point along the face's axis
+    WrongFace,
+}
+
+/// Accelerometer correction, `matrix * (accel - offset)`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct AccelCalibration {
+    pub offset: Vec3,
+    /// Per axis scale on the diagonal, cross axis sensitivity off it
+    pub matrix: Matrix3<f32>,
+}
+
+impl AccelCalibration {
+    pub fn apply(&self, accel: &Vec3) -> Vec3 {
+        self.matrix * (accel - self.offset)
+    }
+}
+
+/// Collects the average accel reading of every face and solves for an `AccelCalibration`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct AccelCalibrator {
+    samples: [u16; 6],
+    faces: [Stats; 6],
+}
+
+impl AccelCalibrator {
+    pub fn new() -> Self {
+        Self {
+            samples: [0; 6],
+            faces: [Stats::new(); 6],
+        }
+    }
+
+    /// Adds an uncalibrated accel reading in g taken while resting on `face`
+    pub fn add(&mut self, face: Face, accel: &Vec3) {
+        let i = face.index();
+        self.samples[i] += 1;
+        self.faces[i].add(self.samples[i], accel);
+    }
+
+    pub fn clear(&mut self, face: Face) {
+        self.samples[face.index()] = 0;
+        self.faces[face.index()] = Stats::new();
+    }
+
+    /// Checks the samples collected for `face`, a face that fails should be cleared and collected again
+    pub fn check(&self, face: Face) -> Result<(), FaceError> {
+        let i = face.index();
+        if self.samples[i] < 2 || self.faces[i].max_std(self.samples[i]) > ACCEL_STILL_STD {
+            return Err(FaceError::Moved);
+        }
+        let mean = self.faces[i].mean;
+        if mean.norm() == 0.0 || mean.normalize().dot(&face.gravity()) < FACE_ALIGNMENT {
+            return Err(FaceError::WrongFace);
+        }
+        Ok(())
+    }
+
+    /// Per axis offset and scale from opposite faces, or with `cross_axis` a least squares
+    /// fit of the full matrix. `None` until every face passed `check`
+    pub fn solve(&self, cross_axis: bool) -> Option<AccelCalibration> {
+        if Face::ALL.iter().any(|face| self.check(*face).is_err()) {
+            return None;
+        }
+
+        if cross_axis {
+            return self.solve_cross_axis();
+        }
+
+        let mut offset = Vector3::zeros();
+        let mut scale = Vector3::zeros();
+        for (axis, (up, down)) in [(Face::XUp, Face::XDown), (Face::YUp, Face::YDown), (Face::ZUp, Face::ZDown)].iter().enumerate() {
+            let up = self.faces[up.index()].mean[axis];
+            let down = self.faces[down.index()].mean[axis];
+            offset[axis] = (up + down) / 2.0;
+            scale[axis] = 2.0 / (up - down);
+        }
+
+        Some(AccelCalibration {
+            offset,
+            matrix: Matrix3::from_diagonal(&scale),
+        })
+    }
+
+    /// Fits `gravity = matrix * accel + b` row by row over all six faces, then
+    /// `offset = -matrix⁻¹ * b`
+    fn solve_cross_axis(&self) -> Option<AccelCalibration> {
+        let mut normal = Matrix4::zeros();
+        let mut rhs = Matrix4x3::zeros();
+        for face in Face::ALL {
+            let mean = self.faces[face.index()].mean;
+            let x = Vector4::new(mean.x, mean.y, mean.z, 1.0);
+            normal += x * x.transpose();
+            rhs += x * face.gravity().transpose();
+        }
+
+        // every column holds one row of [matrix | b]
+        let solution = normal.try_inverse()? * rhs;
+        let matrix = solution.fixed_rows::<3>(0).transpose();
+        let b = solution.row(3).transpose();
+        let offset = -(matrix.try_inverse()? * b);
+
+        Some(AccelCalibration {
+            offset,
+            matrix,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds every face the raw reading a sensor with `calibration` gives when it rests on it
+    fn calibrator(calibration: &AccelCalibration) -> AccelCalibrator {
+        let raw_per_g = calibration.matrix.try_inverse().unwrap();
+        let mut calibrator = AccelCalibrator::new();
+        for face in Face::ALL {
+            let raw = raw_per_g * face.gravity() + calibration.offset;
+            for _ in 0..4 {
+                calibrator.add(face, &raw);
+            }
+        }
+        calibrator
+    }
+
+    fn assert_close(solved: &AccelCalibration, expected: &AccelCalibration) {
+        assert!((solved.offset - expected.offset).amax() < 1e-4, "{:?}", solved.offset);
+        assert!((solved.matrix - expected.matrix).amax() < 1e-4, "{:?}", solved.matrix);
+    }
+
+    #[test]
+    fn solves_offset_and_scale() {
+        let expected = AccelCalibration {
+            offset: Vector3::new(0.03, -0.05, 0.02),
+            matrix: Matrix3::from_diagonal(&Vector3::new(1.02, 0.97, 1.01)),
+        };
+        let calibrator = calibrator(&expected);
+        assert_close(&calibrator.solve(false).unwrap(), &expected);
+        assert_close(&calibrator.solve(true).unwrap(), &expected);
+    }
+
+    #[test]
+    fn solves_cross_axis_sensitivity() {
+        let expected = AccelCalibration {
+            offset: Vector3::new(-0.04, 0.01, 0.06),
+            matrix: Matrix3::new(1.02, 0.015, -0.01, 0.02, 0.98, 0.005, -0.012, 0.018, 1.01),
+        };
+        assert_close(&calibrator(&expected).solve(true).unwrap(), &expected);
+    }
+
+    #[test]
+    fn needs_every_face() {
+        let expected = AccelCalibration {
+            offset: Vector3::zeros(),
+            matrix: Matrix3::identity(),
+        };
+        let mut calibrator = calibrator(&expected);
+        calibrator.clear(Face::YDown);
+        assert_eq!(calibrator.solve(true), None);
+        assert_eq!(calibrator.check(Face::YDown), Err(FaceError::Moved));
+
+        // resting on the wrong face
+        for _ in 0..4 {
+            calibrator.add(Face::YDown, &Face::ZUp.gravity());
+        }
+        assert_eq!(calibrator.check(Face::YDown), Err(FaceError::WrongFace));
+    }
+}
@@ -0,0 +1,132 @@
+use fusion_rs::nalgebra::Vector3;
+use fusion_rs::Vec3;
+
+/// Temperature span in °C below which the slope can't be fitted reliably
+const MIN_TEMP_SPAN: f32 = 2.0;
+
+/// Linear gyro zero rate offset over temperature, `bias = offset + slope * temp`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GyroTempModel {
+    /// Offset in °/s at 0°C
+    pub offset: Vec3,
+    /// Drift in °/s per °C
+    pub slope: Vec3,
+}
+
+impl GyroTempModel {
+    pub fn bias(&self, temp: f32) -> Vec3 {
+        self.offset + self.slope * temp
+    }
+}
+
+/// Least squares fit of a `GyroTempModel` from samples taken while the tracker is still
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct GyroTempLearner {
+    samples: u32,
+    // sums are taken relative to the first temperature to keep f32 precision
+    reference: f32,
+    min_temp: f32,
+    max_temp: f32,
+    sum_t: f32,
+    sum_tt: f32,
+    sum_g: Vec3,
+    sum_tg: Vec3,
+}
+
+impl GyroTempLearner {
+    pub fn new() -> Self {
+        Self {
+            samples: 0,
+            reference: 0.0,
+            min_temp: f32::MAX,
+            max_temp: f32::MIN,
+            sum_t: 0.0,
+            sum_tt: 0.0,
+            sum_g: Vector3::zeros(),
+            sum_tg: Vector3::zeros(),
+        }
+    }
+
+    pub fn add(&mut self, temp: f32, gyro: &Vec3) {
+        if self.samples == 0 {
+            self.reference = temp;
+        }
+
+        let t = temp - self.reference;
+        self.samples += 1;
+        self.min_temp = self.min_temp.min(temp);
+        self.max_temp = self.max_temp.max(temp);
+        self.sum_t += t;
+        self.sum_tt += t * t;
+        self.sum_g += gyro;
+        self.sum_tg += gyro * t;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Fits the model, `None` until the samples cover enough temperature range for a slope
+    pub fn fit(&self) -> Option<GyroTempModel> {
+        if self.samples < 2 || self.max_temp - self.min_temp < MIN_TEMP_SPAN {
+            return None;
+        }
+
+        let n = self.samples as f32;
+        let mean_t = self.sum_t / n;
+        let mean_g = self.sum_g / n;
+        let var_t = self.sum_tt / n - mean_t * mean_t;
+        let slope = (self.sum_tg / n - mean_g * mean_t) / var_t;
+
+        // move the intercept from the reference temperature back to 0°C
+        let offset = mean_g - slope * (mean_t + self.reference);
+        Some(GyroTempModel { offset, slope })
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_linear_drift() {
+        let model = GyroTempModel {
+            offset: Vector3::new(1.5, -0.8, 0.3),
+            slope: Vector3::new(0.02, -0.05, 0.01),
+        };
+        let mut learner = GyroTempLearner::new();
+        // warming up on someone's head, 25 to 40 °C
+        for i in 0..=150 {
+            let temp = 25.0 + i as f32 * 0.1;
+            learner.add(temp, &model.bias(temp));
+        }
+        assert_eq!(learner.samples(), 151);
+
+        let fitted = learner.fit().unwrap();
+        assert!((fitted.slope - model.slope).amax() < 1e-4, "{:?}", fitted.slope);
+        assert!((fitted.offset - model.offset).amax() < 1e-3, "{:?}", fitted.offset);
+        assert!((fitted.bias(32.0) - model.bias(32.0)).amax() < 1e-4);
+    }
+
+    #[test]
+    fn needs_a_temperature_span() {
+        let mut learner = GyroTempLearner::new();
+        assert_eq!(learner.fit(), None);
+
+        for _ in 0..100 {
+            learner.add(30.0, &Vector3::new(1.0, 2.0, 3.0));
+        }
+        assert_eq!(learner.fit(), None);
+
+        learner.add(30.0 + MIN_TEMP_SPAN, &Vector3::new(1.0, 2.0, 3.0));
+        assert!(learner.fit().is_some());
+
+        learner.reset();
+        assert_eq!(learner.samples(), 0);
+        assert_eq!(learner.fit(), None);
+    }
+}
@@ -1,53 +1,111 @@
 pub const MPU6050_ADDR: u8 = 0x68;
+/// Second MPU6050 with AD0 pulled high
+pub const MPU6050_ADDR_AD0_HIGH: u8 = 0x69;
 pub const MPU6050_WHOAMI_ADDR: u8 = 0x75;
+pub const MPU6050_WHOAMI: u8 = 0x68;
+pub const MPU6500_WHOAMI: u8 = 0x70;
+pub const MPU9250_WHOAMI: u8 = 0x71;
+pub const MPU9255_WHOAMI: u8 = 0x73;
 
-pub const MASTER_MODE_ENABLE: u8 = 0x6A;
-pub const MASTER_MODE_ENABLE_BIT: u8 = 5;
+// bit fields are defined in registers.rs
+pub const SELF_TEST_X: u8 = 0x0D;
+pub const SAMPLE_RATE_DIVIDER: u8 = 0x19;
+pub const CONFIG: u8 = 0x1A;
+pub const GYRO_CONFIG: u8 = 0x1B;
+pub const ACCEL_CONFIG: u8 = 0x1C;
+pub const ACCEL_CONFIG_2: u8 = 0x1D;
+pub const FIFO_ENABLE: u8 = 0x23;
+pub const I2C_MASTER_CONTROL: u8 = 0x24;
+pub const I2C_SLAVE0_ADDR: u8 = 0x25;
+pub const I2C_SLAVE0_REG: u8 = 0x26;
+pub const I2C_SLAVE0_CONTROL: u8 = 0x27;
+pub const INT_PIN_CONFIG: u8 = 0x37;
+pub const INT_ENABLE: u8 = 0x38;
+pub const INT_STATUS: u8 = 0x3A;
+pub const ACCEL_GYRO_READ: u8 = 0x3B;
+pub const EXT_SENS_DATA: u8 = 0x49;
+pub const USER_CONTROL: u8 = 0x6A;
+pub const POWER_MGMT_1: u8 = 0x6B;
+pub const DMP_BANK_SELECT: u8 = 0x6D;
+pub const DMP_MEMORY_START: u8 = 0x6E;
+pub const DMP_MEMORY_READ_WRITE: u8 = 0x6F;
+pub const DMP_PROGRAM_START: u8 = 0x70;
+pub const FIFO_COUNT: u8 = 0x72;
+pub const FIFO_READ_WRITE: u8 = 0x74;
 
-pub const I2C_BYPASS_ENABLE: u8 = 0x37;
-pub const I2C_BYPASS_ENABLE_BIT: u8 = 1;
+pub const DMP_PROGRAM_START_ADDR: u16 = 0x0400;
+pub const DMP_BANK_SIZE: usize = 256;
+/// Bytes written to DMP memory per transfer, divides the bank size
+pub const DMP_CHUNK_SIZE: usize = 16;
 
-pub const POWER_MGMT_1: u8 = 0x6B;
+pub const FIFO_SIZE: u16 = 1024;
+pub const MPU6500_FIFO_SIZE: u16 = 512;
+/// Size of one accel, temperature and gyro sample
+pub const ACCEL_GYRO_PACKET: usize = 14;
+
+pub const HMC5883L_ADDR: u8 = 0x1E;
+pub const HMC5883L_WHOAMI_ADDR: u8 = 0x0A;
+pub const HMC5883L_CONFIG_A: u8 = 0x00;
+pub const HMC5883L_CONFIG_B: u8 = 0x01;
+pub const HMC5883L_MODE_REG: u8 = 0x02;
+pub const MAG_READ: u8 = 0x03;
+pub const HMC5883L_STATUS_REG: u8 = 0x09;
+pub const HMC5883L_STATUS_READY_BIT: u8 = 0;
 
-pub const SLEEP_ENABLED_BIT: u8 = 6;
+pub const QMC5883L_ADDR: u8 = 0x0D;
+pub const QMC5883L_DATA: u8 = 0x00;
+pub const QMC5883L_STATUS_REG: u8 = 0x06;
+pub const QMC5883L_STATUS_READY_BIT: u8 = 0;
+pub const QMC5883L_CONTROL_1: u8 = 0x09;
+pub const QMC5883L_CONTROL_2: u8 = 0x0A;
+pub const QMC5883L_SET_RESET_PERIOD: u8 = 0x0B;
+pub const QMC5883L_SET_RESET_PERIOD_DEFAULT: u8 = 0x01;
+pub const QMC5883L_CHIP_ID_ADDR: u8 = 0x0D;
+pub const QMC5883L_CHIP_ID: u8 = 0xFF;
 
-pub const CLOCK_SELECT_BIT: u8 = 2;
-pub const CLOCK_SELECT_LENGTH: u8 = 3;
-pub const CLOCK_SOURCE: u8 = 1;
+pub const ICM20948_WHOAMI_ADDR: u8 = 0x00;
+pub const ICM20948_WHOAMI: u8 = 0xEA;
+pub const ICM20948_BANK_SELECT: u8 = 0x7F;
+pub const ICM20948_OUTPUT_RATE: u16 = 1125;
 
-pub const GYRO_CONFIG: u8 = 0x1B;
-pub const GYRO_CONFIG_SELECT_BIT: u8 = 4;
-pub const GYRO_CONFIG_SELECT_LENGTH: u8 = 2;
-pub const GYRO_CONFIG_250: u8 = 0;
+// bank 0
+pub const ICM20948_USER_CONTROL: u8 = 0x03;
+pub const ICM20948_POWER_MGMT_1: u8 = 0x06;
+pub const ICM20948_POWER_MGMT_2: u8 = 0x07;
+pub const ICM20948_ACCEL_GYRO_ON: u8 = 0x00;
+pub const ICM20948_INT_PIN_CONFIG: u8 = 0x0F;
+pub const ICM20948_INT_ENABLE_1: u8 = 0x11;
+pub const ICM20948_ACCEL_GYRO_READ: u8 = 0x2D;
 
-pub const ACCEL_CONFIG: u8 = 0x1C;
-pub const ACCEL_CONFIG_SELECT_BIT: u8 = 4;
-pub const ACCEL_CONFIG_SELECT_LENGTH: u8 = 2;
-pub const ACCEL_CONFIG_2G: u8 = 0;
+// bank 2
+pub const ICM20948_GYRO_SAMPLE_RATE_DIVIDER: u8 = 0x00;
+pub const ICM20948_GYRO_CONFIG_1: u8 = 0x01;
+pub const ICM20948_ACCEL_SAMPLE_RATE_DIVIDER_2: u8 = 0x11;
+pub const ICM20948_ACCEL_CONFIG: u8 = 0x14;
 
-pub const ACCEL_GYRO_READ: u8 = 0x3B;
-pub const MAG_READ: u8 = 0x03;
+pub const AK8963_ADDR: u8 = 0x0C;
+pub const AK8963_WHOAMI_ADDR: u8 = 0x00;
+pub const AK8963_WHOAMI: u8 = 0x48;
+pub const AK8963_STATUS_1: u8 = 0x02;
+pub const AK8963_STATUS_READY_BIT: u8 = 0;
+pub const AK8963_DATA: u8 = 0x03;
+pub const AK8963_CONTROL_1: u8 = 0x0A;
+pub const AK8963_CONTROL_2: u8 = 0x0B;
+pub const AK8963_SENSITIVITY_ADJUST: u8 = 0x10;
 
-pub const HMC5883L_ADDR: u8 = 0x1E;
-pub const HMC5883L_WHOAMI_ADDR: u8 = 0x0A;
-pub const HMC5883L_CONFIG_A: u8 = 0;
-pub const HMC5883L_CONFIG_B: u8 = 1;
-
-pub const HMC5883L_AVERAGING_8: u8 = 0x03;
-pub const HMC5883L_CRA_AVERAGE_BIT: u8 = 6;
-pub const HMC5883L_CRA_AVERAGE_LENGTH: u8 = 2;
-pub const HMC5883L_RATE_15: u8 = 0x04;
-pub const HMC5883L_CRA_RATE_BIT: u8 = 4;
-pub const HMC5883L_CRA_RATE_LENGTH: u8 = 3;
-pub const HMC5883L_BIAS_NORMAL: u8 = 0x00;
-pub const HMC5883L_CRA_BIAS_BIT: u8 = 1;
-pub const HMC5883L_CRA_BIAS_LENGTH: u8 = 2;
-
-pub const HMC5883L_GAIN: u8 = 1;
-pub const HMC5883L_GAIN_BIT: u8 = 7;
-pub const HMC5883L_GAIN_LENGTH: u8 = 3;
-
-pub const HMC5883L_MODE_SINGLE: u8 = 1;
-pub const HMC5883L_MODE_REG: u8 = 0x02;
-pub const HMC5883L_MODE_REG_BIT: u8 = 1;
-pub const HMC5883L_MODE_REG_LENGTH: u8 = 2;
+pub const AK09916_ADDR: u8 = 0x0C;
+pub const AK09916_WHOAMI_ADDR: u8 = 0x01;
+pub const AK09916_WHOAMI: u8 = 0x09;
+pub const AK09916_STATUS_1: u8 = 0x10;
+pub const AK09916_STATUS_READY_BIT: u8 = 0;
+pub const AK09916_DATA: u8 = 0x11;
+pub const AK09916_CONTROL_2: u8 = 0x31;
+pub const AK09916_CONTROL_3: u8 = 0x32;
+
+pub const BMP180_ADDR: u8 = 0x77;
+pub const BMP180_CHIP_ID_ADDR: u8 = 0xD0;
+pub const BMP180_CHIP_ID: u8 = 0x55;
+pub const BMP180_CALIBRATION: u8 = 0xAA;
+pub const BMP180_CALIBRATION_LENGTH: usize = 22;
+pub const BMP180_CONTROL: u8 = 0xF4;
+pub const BMP180_OUTPUT: u8 = 0xF6;
@@ -0,0 +1,131 @@
+use core::fmt::{self, Write};
+
+use embedded_hal_async::i2c::I2c;
+
+use crate::constants::*;
+use crate::imu::Imu;
+use crate::magnetometer::Magnetometer;
+
+/// First and last address that isn't reserved by the I2C spec
+const FIRST_ADDR: u8 = 0x08;
+const LAST_ADDR: u8 = 0x77;
+/// Largest register dump
+const MAX_DUMP: usize = 128;
+/// Registers per line of a dump
+const DUMP_LINE: usize = 16;
+
+/// Chip recognised on the bus
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Chip {
+    Imu(Imu),
+    Magnetometer(Magnetometer),
+    Bmp180,
+}
+
+impl Chip {
+    /// First register and length of the dump
+    fn registers(&self) -> (u8, usize) {
+        match self {
+            // ICM-20948 bank 0 only
+            Chip::Imu(_) => (0x00, 0x80),
+            Chip::Magnetometer(Magnetometer::Hmc5883l) => (0x00, 0x0D),
+            Chip::Magnetometer(Magnetometer::Qmc5883l) => (0x00, 0x0E),
+            Chip::Magnetometer(Magnetometer::Ak8963) => (0x00, 0x13),
+            Chip::Magnetometer(Magnetometer::Ak09916) => (0x00, 0x33),
+            // calibration EEPROM up to the output registers
+            Chip::Bmp180 => (BMP180_CALIBRATION, 0x100 - BMP180_CALIBRATION as usize),
+        }
+    }
+}
+
+/// Whether a device acknowledges `addr`
+pub async fn probe<I: I2c>(i2c: &mut I, addr: u8) -> bool {
+    let mut rx_buffer = [0u8; 1];
+    i2c.read(addr, &mut rx_buffer).await.is_ok()
+}
+
+/// Identifies the chip at `addr` from its id registers
+pub async fn identify<I: I2c>(i2c: &mut I, addr: u8) -> Option<Chip> {
+    match addr {
+        MPU6050_ADDR | MPU6050_ADDR_AD0_HIGH => {
+            if let Some(imu) = read_byte(i2c, addr, MPU6050_WHOAMI_ADDR).await.and_then(Imu::from_who_am_i) {
+                return Some(Chip::Imu(imu));
+            }
+            match read_byte(i2c, addr, ICM20948_WHOAMI_ADDR).await {
+                Some(ICM20948_WHOAMI) => Some(Chip::Imu(Imu::Icm20948)),
+                _ => None,
+            }
+        }
+        HMC5883L_ADDR => {
+            let mut id = [0u8; 3];
+            match i2c.write_read(addr, &[HMC5883L_WHOAMI_ADDR], &mut id).await {
+                Ok(_) if &id == b"H43" => Some(Chip::Magnetometer(Magnetometer::Hmc5883l)),
+                _ => None,
+            }
+        }
+        BMP180_ADDR => match read_byte(i2c, addr, BMP180_CHIP_ID_ADDR).await {
+            Some(BMP180_CHIP_ID) => Some(Chip::Bmp180),
+            _ => None,
+        },
+        _ => {
+            for mag in [Magnetometer::Qmc5883l, Magnetometer::Ak8963, Magnetometer::Ak09916] {
+                if mag.addr() != addr {
+                    continue;
+                }
+                if let Some((reg, id)) = mag.chip_id() {
+                    if read_byte(i2c, addr, reg).await == Some(id) {
+                        return Some(Chip::Magnetometer(mag));
+                    }
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Hex dump of `length` registers from `start`, reading clears status and FIFO registers
+/// just like a normal read would
+pub async fn dump<I: I2c, W: Write>(i2c: &mut I, addr: u8, start: u8, length: usize, out: &mut W) -> fmt::Result {
+    let mut rx_buffer = [0u8; MAX_DUMP];
+    let rx_buffer = &mut rx_buffer[..length.min(MAX_DUMP)];
+    if i2c.write_read(addr, &[start], rx_buffer).await.is_err() {
+        return writeln!(out, "    read failed");
+    }
+
+    for (line, values) in rx_buffer.chunks(DUMP_LINE).enumerate() {
+        write!(out, "    {:02X}:", start as usize + line * DUMP_LINE)?;
+        for value in values {
+            write!(out, " {:02X}", value)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Scans every address, names the known chips and dumps their registers
+pub async fn report<I: I2c, W: Write>(i2c: &mut I, out: &mut W) -> fmt::Result {
+    writeln!(out, "i2c scan {:#04X}..{:#04X}", FIRST_ADDR, LAST_ADDR)?;
+    let mut found = 0;
+    for addr in FIRST_ADDR..=LAST_ADDR {
+        if !probe(i2c, addr).await {
+            continue;
+        }
+        found += 1;
+
+        match identify(i2c, addr).await {
+            Some(chip) => {
+                writeln!(out, "{:#04X} {:?}", addr, chip)?;
+                let (start, length) = chip.registers();
+                dump(i2c, addr, start, length, out).await?;
+            }
+            None => writeln!(out, "{:#04X} unknown", addr)?,
+        }
+    }
+    writeln!(out, "{} devices", found)
+}
+
+async fn read_byte<I: I2c>(i2c: &mut I, addr: u8, reg: u8) -> Option<u8> {
+    let mut rx_buffer = [0u8; 1];
+    i2c.write_read(addr, &[reg], &mut rx_buffer).await.ok()?;
+    Some(rx_buffer[0])
+}
@@ -0,0 +1,35 @@
+use fusion_rs::nalgebra::{Quaternion, UnitQuaternion};
+
+/// Size of a packet of the 6.12 firmware, quaternion then accel and gyro
+pub const DMP_PACKET_SIZE: usize = 28;
+/// Rate the 6.12 firmware expects its input at, from the 1kHz DLPF output
+pub const DMP_SAMPLE_RATE: u16 = 200;
+/// Quaternion components are fixed point with 30 fractional bits
+const Q30: f32 = (1u32 << 30) as f32;
+
+/// Where orientation comes from
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum DmpMode {
+    /// Software fusion only
+    Off,
+    /// Software fusion drives `MovementData`, the DMP runs alongside, see `Gy87::dmp_orientation`
+    Compare,
+    /// The DMP quaternion drives `MovementData` and software fusion is skipped
+    Primary,
+}
+
+/// InvenSense DMP image, the 6.12 MotionApps firmware
+#[derive(PartialEq, Copy, Clone)]
+pub struct DmpFirmware(pub &'static [u8]);
+
+impl core::fmt::Debug for DmpFirmware {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "DmpFirmware({} bytes)", self.0.len())
+    }
+}
+
+/// Orientation from a DMP packet, the 6 axis quaternion has no magnetometer so yaw drifts
+pub fn decode_quaternion(packet: &[u8]) -> UnitQuaternion<f32> {
+    let read = |at: usize| i32::from_be_bytes(packet[at..at + 4].try_into().unwrap()) as f32 / Q30;
+    UnitQuaternion::from_quaternion(Quaternion::new(read(0), read(4), read(8), read(12)))
+}
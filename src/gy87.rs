@@ -1,22 +1,392 @@
 use core::f32::consts::PI;
 
-use embassy_stm32::i2c::I2c;
-use embassy_stm32::peripherals::I2C1;
-use embassy_stm32::peripherals::{DMA1_CH0, DMA1_CH6};
-use embassy_time::Instant;
-use fusion_rs::nalgebra::Vector3;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use embedded_hal_async::digital::Wait;
+use embedded_hal_async::i2c::I2c;
+use fusion_rs::nalgebra::{UnitQuaternion, Vector3};
 use fusion_rs::{Ahrs, Vec3};
-use rtt_target::rprintln;
 
+use crate::bmp180::Bmp180;
+use crate::calibration::{AccelCalibration, AccelCalibrator, Face, FaceError, GyroCalibrationStep, GyroCalibrator};
+use crate::compensation::{GyroTempLearner, GyroTempModel};
 use crate::constants::*;
+use crate::diagnostics;
+use crate::dmp::{decode_quaternion, DmpFirmware, DmpMode, DMP_PACKET_SIZE, DMP_SAMPLE_RATE};
+use crate::imu::{Imu, ImuDriver, MpuDriver};
+use crate::magnetometer::{Hmc5883lDriver, MagDriver, Magnetometer, MAX_MAG_PACKET};
+use crate::orientation::Mounting;
+use crate::recovery::{BusRecovery, RecoveryPolicy, RecoveryStep};
+use crate::redundancy::{ImuHealth, Redundancy};
+use crate::registers;
+use crate::registers::*;
 use crate::util::{convert_accel, convert_gyro};
+use crate::validation::{SampleQuality, Validator};
 
-type I2cType<'a> = I2c<'a, I2C1, DMA1_CH0, DMA1_CH6>;
-
-pub struct Gy87<'a> {
-    i2c: I2cType<'a>,
+pub struct Gy87<I> {
+    i2c: I,
     // imu: Madgwick<f64>,
-    imu: Ahrs
+    imu: Ahrs,
+    config: Gy87Config,
+    gyro_range: GyroRange,
+    accel_range: AccelRange,
+    gyro_calm_samples: u16,
+    gyro_settle_until: Option<Instant>,
+    last_gyro: Vec3,
+    mag_self_test: Option<MagSelfTest>,
+    mag_gain: Vec3,
+    /// Factory sensitivity adjustment the magnetometer driver reported
+    mag_sensitivity: Vec3,
+    mpu_self_test: Option<MpuSelfTest>,
+    last_mag_raw: Option<[i16; 3]>,
+    last_mag_at: Option<Instant>,
+    gyro_temp_model: Option<GyroTempModel>,
+    gyro_temp_learner: GyroTempLearner,
+    chip: Imu,
+    /// Address the IMU answered at
+    imu_addr: u8,
+    mag: Magnetometer,
+    baro: Option<Bmp180<I>>,
+    consecutive_errors: u16,
+    /// `consecutive_errors` up to which every recovery step ran
+    recovered_errors: u16,
+    redundancy: Option<Redundancy>,
+    validator: Validator,
+    dmp_orientation: Option<UnitQuaternion<f32>>,
+    gyro_bias: Vec3,
+    gyro_calibrated: bool,
+    accel_calibration: Option<AccelCalibration>,
+}
+
+/// Raw gyro magnitude treated as close to saturation
+const GYRO_SATURATION_THRESHOLD: u16 = 30_000;
+/// A reading must stay below this fraction of the next lower range before stepping down
+const GYRO_STEP_DOWN_FRACTION: f32 = 0.5;
+/// Consecutive calm samples required before stepping down
+const GYRO_STEP_DOWN_SAMPLES: u16 = 200;
+/// Time for a new gyro range to show up in the output registers
+const GYRO_RANGE_SETTLE: Duration = Duration::from_millis(5);
+
+/// HMC5883L self test field in LSB at gain 5 (390 LSB/Ga), 1.16 Ga on X/Y and 1.08 Ga on Z
+const HMC_SELF_TEST_EXPECTED: [f32; 3] = [452.4, 452.4, 421.2];
+/// Datasheet limits for the self test field at gain 5
+const HMC_SELF_TEST_MIN: i16 = 243;
+const HMC_SELF_TEST_MAX: i16 = 575;
+
+/// Largest allowed change from factory trim of the MPU6050 self test response
+const MPU_SELF_TEST_TOLERANCE: f32 = 0.14;
+/// Samples averaged for each half of the MPU6050 self test
+const MPU_SELF_TEST_SAMPLES: u16 = 50;
+/// Time for the outputs to settle after toggling self test
+const MPU_SELF_TEST_SETTLE: Duration = Duration::from_millis(50);
+
+/// Packets read from the FIFO in a single transfer
+const FIFO_BURST_PACKETS: usize = 8;
+
+/// Windows the tracker may move during before gyro calibration gives up
+const GYRO_CALIBRATION_ATTEMPTS: u16 = 10;
+
+/// Missed samples after which the data ready interrupt is considered dead
+const INT_TIMEOUT_SAMPLES: u32 = 10;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum GyroRange {
+    Dps250,
+    Dps500,
+    Dps1000,
+    Dps2000,
+}
+
+impl GyroRange {
+    /// Full scale in degrees per second
+    pub fn full_scale(&self) -> f32 {
+        match self {
+            GyroRange::Dps250 => 250.0,
+            GyroRange::Dps500 => 500.0,
+            GyroRange::Dps1000 => 1000.0,
+            GyroRange::Dps2000 => 2000.0,
+        }
+    }
+
+    pub fn step_up(&self) -> Option<GyroRange> {
+        match self {
+            GyroRange::Dps250 => Some(GyroRange::Dps500),
+            GyroRange::Dps500 => Some(GyroRange::Dps1000),
+            GyroRange::Dps1000 => Some(GyroRange::Dps2000),
+            GyroRange::Dps2000 => None,
+        }
+    }
+
+    pub fn step_down(&self) -> Option<GyroRange> {
+        match self {
+            GyroRange::Dps250 => None,
+            GyroRange::Dps500 => Some(GyroRange::Dps250),
+            GyroRange::Dps1000 => Some(GyroRange::Dps500),
+            GyroRange::Dps2000 => Some(GyroRange::Dps1000),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum AccelRange {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelRange {
+    /// Full scale in g
+    pub fn full_scale(&self) -> f32 {
+        match self {
+            AccelRange::G2 => 2.0,
+            AccelRange::G4 => 4.0,
+            AccelRange::G8 => 8.0,
+            AccelRange::G16 => 16.0,
+        }
+    }
+}
+
+/// Digital low pass filter setting, named after the accelerometer bandwidth
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum DlpfBandwidth {
+    Hz260,
+    Hz184,
+    Hz94,
+    Hz44,
+    Hz21,
+    Hz10,
+    Hz5,
+}
+
+impl DlpfBandwidth {
+    /// Gyro bandwidth in Hz, slightly different from the accelerometer one
+    pub fn gyro_bandwidth(&self) -> f32 {
+        match self {
+            DlpfBandwidth::Hz260 => 256.0,
+            DlpfBandwidth::Hz184 => 188.0,
+            DlpfBandwidth::Hz94 => 98.0,
+            DlpfBandwidth::Hz44 => 42.0,
+            DlpfBandwidth::Hz21 => 20.0,
+            DlpfBandwidth::Hz10 => 10.0,
+            DlpfBandwidth::Hz5 => 5.0,
+        }
+    }
+
+    /// Gyro bandwidth in Hz of the same DLPF code on the ICM-20948
+    pub fn icm_gyro_bandwidth(&self) -> f32 {
+        match self {
+            DlpfBandwidth::Hz260 => 196.6,
+            DlpfBandwidth::Hz184 => 151.8,
+            DlpfBandwidth::Hz94 => 119.5,
+            DlpfBandwidth::Hz44 => 51.2,
+            DlpfBandwidth::Hz21 => 23.9,
+            DlpfBandwidth::Hz10 => 11.6,
+            DlpfBandwidth::Hz5 => 5.7,
+        }
+    }
+
+    /// Rate the sample rate divider runs off, the DLPF drops it from 8kHz to 1kHz
+    pub fn gyro_output_rate(&self) -> u16 {
+        match self {
+            DlpfBandwidth::Hz260 => 8000,
+            _ => 1000,
+        }
+    }
+}
+
+/// Magnetometer output rate in continuous measurement mode, the QMC5883L runs at the
+/// closest rate at or above the selected one
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum MagRate {
+    Hz0_75,
+    Hz1_5,
+    Hz3,
+    Hz7_5,
+    Hz15,
+    Hz30,
+    Hz75,
+}
+
+impl MagRate {
+    /// Time between two measurements, the other magnetometers run at least this fast
+    pub fn period(&self) -> Duration {
+        match self {
+            MagRate::Hz0_75 => Duration::from_micros(1_333_334),
+            MagRate::Hz1_5 => Duration::from_micros(666_667),
+            MagRate::Hz3 => Duration::from_micros(333_334),
+            MagRate::Hz7_5 => Duration::from_micros(133_334),
+            MagRate::Hz15 => Duration::from_micros(66_667),
+            MagRate::Hz30 => Duration::from_micros(33_334),
+            MagRate::Hz75 => Duration::from_micros(13_334),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ConfigError {
+    SampleRateAboveOutputRate(u16),
+    SampleRateNotDivisor(u16),
+    SampleRateTooLow(u16),
+    SampleRateBelowNyquist(u16),
+    DataReadyInterruptDisabled,
+    /// The FIFO and the I2C master are only supported on the MPU6050 family,
+    /// a redundant IMU only on the MPU6050
+    UnsupportedByImu(Imu),
+    /// The FIFO only holds samples of the first MPU6050
+    RedundantImuWithFifo,
+    /// FIFO packets don't record the range they were taken at, so a range switch would
+    /// scale the buffered ones wrong
+    AutoGyroRangeWithFifo,
+    DmpWithoutFirmware,
+    /// The DMP firmware needs a 200 Hz sample rate off the 1 kHz DLPF output
+    DmpSampleRate(u16),
+    /// `altitude_as_z` without a BMP180, see `Gy87::with_baro`
+    AltitudeWithoutBaro,
+    /// The DMP owns the FIFO and a fixed gyro range, so neither `fifo` nor `auto_gyro_range` work with it
+    DmpConflict,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Gy87Config {
+    pub gyro_range: GyroRange,
+    pub accel_range: AccelRange,
+    /// Step the gyro range up near saturation and back down (never below `gyro_range`) once motion calms down,
+    /// not together with `fifo`
+    pub auto_gyro_range: bool,
+    pub dlpf: DlpfBandwidth,
+    /// Output data rate in Hz, has to divide the gyro output rate of the chosen `dlpf`. The
+    /// ICM-20948 divides 1125 Hz down to the closest rate, see `Gy87::sample_rate`
+    pub sample_rate: u16,
+    /// Buffer samples in the MPU6050 FIFO and feed all of them to fusion on `update`
+    pub fifo: bool,
+    /// Drive the MPU6050 INT pin high whenever a new sample is ready, see `Gy87::wait_for_sample`
+    pub data_ready_interrupt: bool,
+    pub mag_rate: MagRate,
+    /// Run the HMC5883L bias self test in `start` and apply its per axis gain factors,
+    /// the QMC5883L has no self test
+    pub mag_self_test: bool,
+    /// Run the MPU6050 factory self test in `start`
+    pub mpu_self_test: bool,
+    /// Let the MPU6050 I2C master poll the HMC5883L so accel, gyro and mag come in one burst
+    pub mag_via_aux: bool,
+    /// Send the relative altitude as `z` in `MovementData`, needs a BMP180 attached with
+    /// `Gy87::with_baro`
+    pub altitude_as_z: bool,
+    pub recovery: RecoveryPolicy,
+    /// Drive a second MPU6050 with AD0 high at 0x69, average both and fall back to the
+    /// healthy one when they disagree or one fails, see `Gy87::imu_health`
+    pub redundant_imu: bool,
+    /// How the board sits in the tracker
+    pub mounting: Mounting,
+    /// Run the MPU6050 DMP next to or instead of software fusion, it forces the gyro
+    /// to 2000 dps and the accelerometer to 2g
+    pub dmp: DmpMode,
+    pub dmp_firmware: Option<DmpFirmware>,
+    /// Samples averaged for the gyro bias in `start`, 0 skips the calibration
+    pub gyro_calibration_samples: u16,
+}
+
+impl Gy87Config {
+    pub fn validate(&self, imu: Imu) -> Result<(), ConfigError> {
+        if !imu.mpu_compatible() && (self.fifo || self.mag_via_aux) {
+            return Err(ConfigError::UnsupportedByImu(imu));
+        }
+        if self.redundant_imu && imu != Imu::Mpu6050 {
+            return Err(ConfigError::UnsupportedByImu(imu));
+        }
+        if self.redundant_imu && self.fifo {
+            return Err(ConfigError::RedundantImuWithFifo);
+        }
+        if self.dmp != DmpMode::Off {
+            if imu != Imu::Mpu6050 {
+                return Err(ConfigError::UnsupportedByImu(imu));
+            }
+            if self.dmp_firmware.is_none() {
+                return Err(ConfigError::DmpWithoutFirmware);
+            }
+            if self.sample_rate != DMP_SAMPLE_RATE || self.dlpf.gyro_output_rate() != 1000 {
+                return Err(ConfigError::DmpSampleRate(self.sample_rate));
+            }
+            if self.fifo || self.auto_gyro_range {
+                return Err(ConfigError::DmpConflict);
+            }
+        }
+        if self.fifo && self.auto_gyro_range {
+            return Err(ConfigError::AutoGyroRangeWithFifo);
+        }
+
+        let output_rate = self.output_rate(imu);
+        if self.sample_rate == 0 || output_rate / self.sample_rate > 256 {
+            return Err(ConfigError::SampleRateTooLow(self.sample_rate));
+        }
+        if self.sample_rate > output_rate {
+            return Err(ConfigError::SampleRateAboveOutputRate(self.sample_rate));
+        }
+        // 1125 Hz has few divisors, the ICM-20948 runs at the closest rate instead
+        if imu != Imu::Icm20948 && output_rate % self.sample_rate != 0 {
+            return Err(ConfigError::SampleRateNotDivisor(self.sample_rate));
+        }
+        // sampling slower than twice the filter bandwidth aliases
+        if self.effective_sample_rate(imu) < 2.0 * self.gyro_bandwidth(imu) {
+            return Err(ConfigError::SampleRateBelowNyquist(self.sample_rate));
+        }
+        Ok(())
+    }
+
+    /// Rate the IMU actually samples at in Hz, `sample_rate` unless the ICM-20948 can't
+    /// divide down to it exactly
+    pub fn effective_sample_rate(&self, imu: Imu) -> f32 {
+        self.output_rate(imu) as f32 / (self.sample_rate_divider(imu) as f32 + 1.0)
+    }
+
+    fn output_rate(&self, imu: Imu) -> u16 {
+        match imu {
+            Imu::Icm20948 => ICM20948_OUTPUT_RATE,
+            _ => self.dlpf.gyro_output_rate(),
+        }
+    }
+
+    fn gyro_bandwidth(&self, imu: Imu) -> f32 {
+        match imu {
+            Imu::Icm20948 => self.dlpf.icm_gyro_bandwidth(),
+            _ => self.dlpf.gyro_bandwidth(),
+        }
+    }
+
+    /// Value of the sample rate divider register that gets closest to `sample_rate`
+    pub fn sample_rate_divider(&self, imu: Imu) -> u8 {
+        let sample_rate = self.sample_rate.max(1);
+        let divisor = match imu {
+            Imu::Icm20948 => (self.output_rate(imu) + sample_rate / 2) / sample_rate,
+            _ => self.output_rate(imu) / sample_rate,
+        };
+        (divisor.clamp(1, 256) - 1) as u8
+    }
+}
+
+impl Default for Gy87Config {
+    fn default() -> Self {
+        Self {
+            gyro_range: GyroRange::Dps250,
+            accel_range: AccelRange::G2,
+            auto_gyro_range: false,
+            dlpf: DlpfBandwidth::Hz44,
+            sample_rate: 200,
+            fifo: false,
+            data_ready_interrupt: false,
+            mag_rate: MagRate::Hz15,
+            mag_self_test: true,
+            mpu_self_test: true,
+            mag_via_aux: false,
+            altitude_as_z: false,
+            recovery: RecoveryPolicy::default(),
+            redundant_imu: false,
+            mounting: Mounting::identity(),
+            dmp: DmpMode::Off,
+            dmp_firmware: None,
+            gyro_calibration_samples: 400,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -30,6 +400,94 @@ struct Coords {
 pub struct AccelGyro {
     pub accel: Vec3,
     pub gyro: Vec3,
+    /// Die temperature in °C
+    pub temp: f32,
+}
+
+/// Result of the HMC5883L positive and negative bias self test, all arrays in x, y, z order
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct MagSelfTest {
+    pub positive: [i16; 3],
+    pub negative: [i16; 3],
+    /// Correction factor bringing each axis to the datasheet self test field
+    pub gain: [f32; 3],
+    pub passed: [bool; 3],
+}
+
+impl MagSelfTest {
+    fn new(positive: [i16; 3], negative: [i16; 3]) -> Self {
+        let mut gain = [1.0; 3];
+        let mut passed = [false; 3];
+        for i in 0..3 {
+            passed[i] = (HMC_SELF_TEST_MIN..=HMC_SELF_TEST_MAX).contains(&positive[i])
+                && (-HMC_SELF_TEST_MAX..=-HMC_SELF_TEST_MIN).contains(&negative[i]);
+            if passed[i] {
+                gain[i] = 2.0 * HMC_SELF_TEST_EXPECTED[i] / (positive[i] as f32 - negative[i] as f32);
+            }
+        }
+
+        Self {
+            positive,
+            negative,
+            gain,
+            passed,
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.passed.iter().all(|p| *p)
+    }
+}
+
+/// Result of the MPU6050 self test, arrays ordered gyro x, y, z then accel x, y, z
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct MpuSelfTest {
+    /// Relative change of the self test response from factory trim
+    pub deviation: [f32; 6],
+    pub passed: [bool; 6],
+}
+
+impl MpuSelfTest {
+    /// `trim` holds the SELF_TEST_X/Y/Z/A registers, `response` the raw output change with self test enabled
+    fn new(trim: [u8; 4], response: [f32; 6]) -> Self {
+        let gyro_code = [trim[0] & 0x1F, trim[1] & 0x1F, trim[2] & 0x1F];
+        let accel_code = [
+            ((trim[0] >> 5) << 2) | ((trim[3] >> 4) & 0x03),
+            ((trim[1] >> 5) << 2) | ((trim[3] >> 2) & 0x03),
+            ((trim[2] >> 5) << 2) | (trim[3] & 0x03),
+        ];
+
+        let mut factory = [0.0f32; 6];
+        for i in 0..3 {
+            if gyro_code[i] != 0 {
+                factory[i] = 25.0 * 131.0 * libm::powf(1.046, gyro_code[i] as f32 - 1.0);
+            }
+            if accel_code[i] != 0 {
+                factory[i + 3] = 4096.0 * 0.34 * libm::powf(0.92 / 0.34, (accel_code[i] as f32 - 1.0) / 30.0);
+            }
+        }
+        // the y gyro trim is specified negative
+        factory[1] = -factory[1];
+
+        let mut deviation = [f32::INFINITY; 6];
+        let mut passed = [false; 6];
+        for i in 0..6 {
+            // a zero trim code means the axis was never trimmed, treat it as failed
+            if factory[i] != 0.0 {
+                deviation[i] = (response[i] - factory[i]) / factory[i];
+                passed[i] = libm::fabsf(deviation[i]) <= MPU_SELF_TEST_TOLERANCE;
+            }
+        }
+
+        Self {
+            deviation,
+            passed,
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.passed.iter().all(|p| *p)
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -40,6 +498,8 @@ pub struct MovementData {
     pub yaw: f64,
     pub pitch: f64,
     pub roll: f64,
+    /// Not serialized
+    pub quality: SampleQuality,
 }
 
 impl MovementData {
@@ -62,9 +522,30 @@ pub enum Gy87Error {
     MpuInit(BusError),
     UpdateGetAccelGyro(BusError),
     UpdateMag(BusError),
+    UpdateFifo(BusError),
+    UpdateBaro(BusError),
+    BaroInit(BusError),
+    DmpInit(BusError),
+    /// Reading the DMP memory back didn't match the firmware
+    DmpFirmwareVerify,
     UpdateError,
+    FifoOverflow,
+    FifoEmpty,
+    InterruptPin,
+    InterruptTimeout,
+    /// The IMU returned the exact same sample for too long
+    FrozenSample,
+    /// Every axis read the same value, e.g. all 0xFF
+    InvalidSample,
+    /// The tracker kept moving during every gyro calibration window
+    GyroNotStill,
+    AccelCalibration(Face, FaceError),
     UnknownMPUDeviceAddr(u8),
     UnknownHMCDeviceAddr([u8; 3]),
+    UnknownBMPDeviceAddr(u8),
+    HmcSelfTestFailed([bool; 3]),
+    MpuSelfTestFailed([bool; 6]),
+    InvalidConfig(ConfigError),
 }
 
 impl core::fmt::Display for Gy87Error {
@@ -75,172 +556,1577 @@ impl core::fmt::Display for Gy87Error {
             Gy87Error::MpuInit(e) => write!(f, "{:?}", e),
             Gy87Error::UpdateGetAccelGyro(e) => write!(f, "{:?}", e),
             Gy87Error::UpdateMag(e) => write!(f, "{:?}", e),
+            Gy87Error::UpdateFifo(e) => write!(f, "{:?}", e),
+            Gy87Error::UpdateBaro(e) => write!(f, "{:?}", e),
+            Gy87Error::BaroInit(e) => write!(f, "{:?}", e),
+            Gy87Error::DmpInit(e) => write!(f, "{:?}", e),
+            Gy87Error::DmpFirmwareVerify => write!(f, "DmpFirmwareVerify"),
             Gy87Error::UpdateError => write!(f, "UpdateError"),
+            Gy87Error::FifoOverflow => write!(f, "FifoOverflow"),
+            Gy87Error::FifoEmpty => write!(f, "FifoEmpty"),
+            Gy87Error::InterruptPin => write!(f, "InterruptPin"),
+            Gy87Error::InterruptTimeout => write!(f, "InterruptTimeout"),
+            Gy87Error::FrozenSample => write!(f, "FrozenSample"),
+            Gy87Error::InvalidSample => write!(f, "InvalidSample"),
+            Gy87Error::GyroNotStill => write!(f, "GyroNotStill"),
+            Gy87Error::AccelCalibration(face, e) => write!(f, "{:?} {:?}", face, e),
             Gy87Error::UnknownMPUDeviceAddr(e) => write!(f, "{:?}", e),
             Gy87Error::UnknownHMCDeviceAddr(e) => write!(f, "{:?}", e),
+            Gy87Error::UnknownBMPDeviceAddr(e) => write!(f, "{:?}", e),
+            Gy87Error::HmcSelfTestFailed(e) => write!(f, "{:?}", e),
+            Gy87Error::MpuSelfTestFailed(e) => write!(f, "{:?}", e),
+            Gy87Error::InvalidConfig(e) => write!(f, "{:?}", e),
         }
     }
 }
 
+impl Gy87Error {
+    /// Whether the error came from the bus or a sensor that stopped sampling, only those
+    /// count towards recovery
+    pub fn is_bus_error(&self) -> bool {
+        matches!(
+            self,
+            Gy87Error::BusError(_)
+                | Gy87Error::FrozenSample
+                | Gy87Error::InvalidSample
+                | Gy87Error::UpdateGetAccelGyro(_)
+                | Gy87Error::UpdateMag(_)
+                | Gy87Error::UpdateFifo(_)
+                | Gy87Error::UpdateBaro(_)
+        )
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub enum BusError {
     BusWrite,
     BusReadWrite,
 }
 
-impl<'a> Gy87<'a> {
-    pub fn new(i2c: I2cType<'a>) -> Self {
+impl<I: I2c> Gy87<I> {
+    pub fn new(i2c: I, config: Gy87Config) -> Self {
         Self {
             i2c,
             // imu: Madgwick::new(0.00728, 0.1),
-            imu: Ahrs::new()
+            imu: Ahrs::new(),
+            config,
+            gyro_range: config.gyro_range,
+            accel_range: config.accel_range,
+            gyro_calm_samples: 0,
+            gyro_settle_until: None,
+            last_gyro: Vector3::zeros(),
+            mag_self_test: None,
+            mag_gain: Vector3::new(1.0, 1.0, 1.0),
+            mag_sensitivity: Vector3::new(1.0, 1.0, 1.0),
+            mpu_self_test: None,
+            last_mag_raw: None,
+            last_mag_at: None,
+            gyro_temp_model: None,
+            gyro_temp_learner: GyroTempLearner::new(),
+            chip: Imu::Mpu6050,
+            imu_addr: MPU6050_ADDR,
+            mag: Magnetometer::Hmc5883l,
+            baro: None,
+            consecutive_errors: 0,
+            recovered_errors: 0,
+            redundancy: None,
+            validator: Validator::new(),
+            dmp_orientation: None,
+            gyro_bias: Vector3::zeros(),
+            gyro_calibrated: false,
+            accel_calibration: None,
         }
     }
 
-    pub fn start(&mut self) -> Result<(), Gy87Error> {
-        // check who am i
-        let who_am_i = self.get_byte(MPU6050_ADDR, MPU6050_WHOAMI_ADDR).map_err(|e| Gy87Error::BusError(e))?;
-        if who_am_i != MPU6050_ADDR {
-            return Err(Gy87Error::UnknownMPUDeviceAddr(who_am_i));
+    /// Reads a BMP180 on its own bus handle alongside the motion sensors, see `altitude`
+    pub fn with_baro(mut self, baro: Bmp180<I>) -> Self {
+        self.baro = Some(baro);
+        self
+    }
+
+    /// Output data rate in Hz of the detected IMU
+    pub fn sample_rate(&self) -> f32 {
+        self.config.effective_sample_rate(self.chip)
+    }
+
+    /// Time between two samples in seconds
+    pub fn sample_period(&self) -> f32 {
+        1.0 / self.sample_rate()
+    }
+
+    fn sample_interval(&self) -> Duration {
+        Duration::from_micros((1_000_000.0 / self.sample_rate()) as u64)
+    }
+
+    pub fn gyro_range(&self) -> GyroRange {
+        self.gyro_range
+    }
+
+    pub fn accel_range(&self) -> AccelRange {
+        self.accel_range
+    }
+
+    pub async fn set_gyro_range(&mut self, range: GyroRange) -> Result<(), BusError> {
+        self.chip.set_gyro_range(&mut self.i2c, self.imu_addr, range).await?;
+        self.modify_secondary(GYRO_FULL_SCALE, range).await;
+        self.gyro_range = range;
+        Ok(())
+    }
+
+    pub async fn set_accel_range(&mut self, range: AccelRange) -> Result<(), BusError> {
+        self.chip.set_accel_range(&mut self.i2c, self.imu_addr, range).await?;
+        self.modify_secondary(ACCEL_FULL_SCALE, range).await;
+        self.accel_range = range;
+        Ok(())
+    }
+
+    pub async fn start(&mut self) -> Result<(), Gy87Error> {
+        self.chip = self.detect_imu().await?;
+        self.config.validate(self.chip).map_err(|e| Gy87Error::InvalidConfig(e))?;
+        if self.config.altitude_as_z && self.baro.is_none() {
+            return Err(Gy87Error::InvalidConfig(ConfigError::AltitudeWithoutBaro));
         }
 
-        self.mpu_init().map_err(|e| Gy87Error::MpuInit(e))?;
-        self.hmc_init().map_err(|e| Gy87Error::HmcInit(e))?;
+        self.imu_init().await.map_err(|e| Gy87Error::MpuInit(e))?;
 
-        let mut hmc_who_am_i = [0u8; 3];
-        self.get_bytes(HMC5883L_ADDR, HMC5883L_WHOAMI_ADDR, &mut hmc_who_am_i).map_err(|e| Gy87Error::BusError(e))?;
-        if hmc_who_am_i[0] != 'H' as u8 || hmc_who_am_i[1] != '4' as u8 || hmc_who_am_i[2] != '3' as u8  {
-            return Err(Gy87Error::UnknownHMCDeviceAddr(hmc_who_am_i));
+        // the self test registers differ on the newer chips
+        if self.config.mpu_self_test && self.chip == Imu::Mpu6050 {
+            let report = self.run_mpu_self_test().await.map_err(|e| Gy87Error::MpuInit(e))?;
+            if !report.ok() {
+                return Err(Gy87Error::MpuSelfTestFailed(report.passed));
+            }
+        }
+
+        if self.config.redundant_imu {
+            let who_am_i = self.get_byte(MPU6050_ADDR_AD0_HIGH, MPU6050_WHOAMI_ADDR).await.map_err(|e| Gy87Error::BusError(e))?;
+            if who_am_i != MPU6050_WHOAMI {
+                return Err(Gy87Error::UnknownMPUDeviceAddr(who_am_i));
+            }
+            self.secondary_init().await.map_err(|e| Gy87Error::MpuInit(e))?;
+            self.redundancy = Some(Redundancy::new());
+        }
+
+        if self.config.dmp != DmpMode::Off {
+            self.dmp_init().await?;
+        }
+
+        self.mag = self.detect_mag().await?;
+        self.mag_init().await.map_err(|e| Gy87Error::HmcInit(e))?;
+
+        if self.config.mag_self_test && self.mag == Magnetometer::Hmc5883l {
+            let report = self.run_mag_self_test().await.map_err(|e| Gy87Error::HmcInit(e))?;
+            if !report.ok() {
+                return Err(Gy87Error::HmcSelfTestFailed(report.passed));
+            }
+            self.mag_gain = Vector3::new(report.gain[0], report.gain[1], report.gain[2]);
+        }
+
+        if self.config.mag_via_aux {
+            self.aux_init().await.map_err(|e| Gy87Error::MpuInit(e))?;
+        }
+
+        if let Some(baro) = &mut self.baro {
+            baro.start().await?;
+        }
+
+        if self.config.gyro_calibration_samples > 0 {
+            // a tracker moved around at power up keeps the zero or stored bias, see `gyro_calibrated`
+            match self.calibrate_gyro().await {
+                Ok(_) | Err(Gy87Error::GyroNotStill) => {}
+                Err(e) => return Err(e),
+            }
         }
 
         Ok(())
     }
 
-    pub fn get_accel_gyro(&mut self) -> Result<AccelGyro, BusError> {
-        let mut rx_buffer = [0u8; 14];
-        self.get_bytes(MPU6050_ADDR, ACCEL_GYRO_READ, &mut rx_buffer)?;
-        Ok(
-            AccelGyro {
-                accel: Vector3::new(
-                    convert_accel(i16::from_be_bytes(rx_buffer[0..2].try_into().unwrap())),
-                    convert_accel(i16::from_be_bytes(rx_buffer[2..4].try_into().unwrap())),
-                    convert_accel(i16::from_be_bytes(rx_buffer[4..6].try_into().unwrap())),
-                ),
-                gyro: Vector3::new(
-                    convert_gyro(i16::from_be_bytes(rx_buffer[8..10].try_into().unwrap())),
-                    convert_gyro(i16::from_be_bytes(rx_buffer[10..12].try_into().unwrap())),
-                    convert_gyro(i16::from_be_bytes(rx_buffer[12..14].try_into().unwrap())),
-                )
+    /// Writes a bus scan with register dumps of every known chip to `out`, e.g. the RTT
+    /// console after `start` failed
+    pub async fn diagnose<W: core::fmt::Write>(&mut self, out: &mut W) -> core::fmt::Result {
+        diagnostics::report(&mut self.i2c, out).await
+    }
+
+    pub fn imu(&self) -> Imu {
+        self.chip
+    }
+
+    pub fn magnetometer(&self) -> Magnetometer {
+        self.mag
+    }
+
+    /// Latest DMP orientation in the tracker frame, `None` until the DMP delivered a packet
+    pub fn dmp_orientation(&self) -> Option<UnitQuaternion<f32>> {
+        self.dmp_orientation
+    }
+
+    /// Health of the MPU6050 at 0x68 and the one at 0x69, `None` without a redundant IMU
+    pub fn imu_health(&self) -> Option<[ImuHealth; 2]> {
+        self.redundancy.map(|redundancy| redundancy.health())
+    }
+
+    /// Probes both IMU addresses and keeps the one that answered in `imu_addr`, with a
+    /// redundant IMU the second MPU6050 sits at 0x69 so only 0x68 is tried
+    async fn detect_imu(&mut self) -> Result<Imu, Gy87Error> {
+        let addrs = if self.config.redundant_imu { &Imu::ADDRS[..1] } else { &Imu::ADDRS[..] };
+        let mut unknown = None;
+        let mut error = BusError::BusReadWrite;
+        for &addr in addrs {
+            // check who am i
+            let who_am_i = match self.get_byte(addr, MPU6050_WHOAMI_ADDR).await {
+                Ok(who_am_i) => who_am_i,
+                Err(e) => {
+                    error = e;
+                    continue;
+                }
+            };
+
+            let imu = match Imu::from_who_am_i(who_am_i) {
+                Some(imu) => Some(imu),
+                // the ICM-20948 keeps its id in the first register of bank 0
+                None => match self.get_byte(addr, ICM20948_WHOAMI_ADDR).await {
+                    Ok(ICM20948_WHOAMI) => Some(Imu::Icm20948),
+                    _ => None,
+                },
+            };
+            if let Some(imu) = imu {
+                self.imu_addr = addr;
+                return Ok(imu);
             }
-        )
+            unknown.get_or_insert(who_am_i);
+        }
+
+        // an unknown chip is reported over an address nothing answered at
+        match unknown {
+            Some(who_am_i) => Err(Gy87Error::UnknownMPUDeviceAddr(who_am_i)),
+            None => Err(Gy87Error::BusError(error)),
+        }
     }
 
-    pub fn get_mag(&mut self) -> Result<Vec3, BusError> {
-        let mut rx_buffer = [0u8; 6];
-        self.get_bytes(HMC5883L_ADDR, MAG_READ, &mut rx_buffer)?;
+    async fn detect_mag(&mut self) -> Result<Magnetometer, Gy87Error> {
+        if let Some(mag) = self.chip.internal_mag() {
+            if self.probe_mag(mag).await {
+                return Ok(mag);
+            }
+        }
+
+        let mut hmc_who_am_i = [0u8; 3];
+        let hmc = self.get_bytes(HMC5883L_ADDR, HMC5883L_WHOAMI_ADDR, &mut hmc_who_am_i).await;
+        if hmc.is_ok() && hmc_who_am_i[0] == 'H' as u8 && hmc_who_am_i[1] == '4' as u8 && hmc_who_am_i[2] == '3' as u8 {
+            return Ok(Magnetometer::Hmc5883l);
+        }
 
-        self.i2c.blocking_write(HMC5883L_ADDR, &[HMC5883L_MODE_REG, HMC5883L_MODE_SINGLE << (HMC5883L_MODE_REG_BIT - HMC5883L_MODE_REG_LENGTH + 1)]).map_err(|_| BusError::BusWrite)?;
+        if self.probe_mag(Magnetometer::Qmc5883l).await {
+            return Ok(Magnetometer::Qmc5883l);
+        }
 
-        Ok(
-            Vector3::new(
-                i16::from_be_bytes(rx_buffer[0..2].try_into().unwrap()) as f32,
-                i16::from_be_bytes(rx_buffer[4..6].try_into().unwrap()) as f32,
-                i16::from_be_bytes(rx_buffer[2..4].try_into().unwrap()) as f32,
-            )
-        )
+        Err(Gy87Error::UnknownHMCDeviceAddr(hmc_who_am_i))
     }
 
-    pub fn update(&mut self, prev: &Instant) -> Result<MovementData, Gy87Error> {
-        let accel_gyro = self.get_accel_gyro().map_err(|e| Gy87Error::UpdateGetAccelGyro(e))?;
-        let mag = self.get_mag().map_err(|e| Gy87Error::UpdateMag(e))?;
-        self.imu.update(accel_gyro.gyro, accel_gyro.accel, mag, prev.elapsed().as_micros() as f32 / 1000000.0);
-        let euler = self.imu.get_euler();
+    async fn probe_mag(&mut self, mag: Magnetometer) -> bool {
+        match mag.chip_id() {
+            Some((reg, id)) => matches!(self.get_byte(mag.addr(), reg).await, Ok(value) if value == id),
+            None => false,
+        }
+    }
+
+    /// Compares the MPU6050 self test response of every axis against its factory trim, restores
+    /// the configured ranges afterwards
+    pub async fn run_mpu_self_test(&mut self) -> Result<MpuSelfTest, BusError> {
+        let gyro_range = self.gyro_range;
+        let accel_range = self.accel_range;
+
+        // factory trim is specified at 250 dps and 8g
+        self.set_gyro_range(GyroRange::Dps250).await?;
+        self.set_accel_range(AccelRange::G8).await?;
+        Timer::after(MPU_SELF_TEST_SETTLE).await;
+        let normal = self.get_raw_average(MPU_SELF_TEST_SAMPLES).await?;
+
+        self.modify(GYRO_SELF_TEST, SelfTest::Xyz).await?;
+        self.modify(ACCEL_SELF_TEST, SelfTest::Xyz).await?;
+        Timer::after(MPU_SELF_TEST_SETTLE).await;
+        let self_test = self.get_raw_average(MPU_SELF_TEST_SAMPLES).await?;
+
+        self.modify(GYRO_SELF_TEST, SelfTest::Off).await?;
+        self.modify(ACCEL_SELF_TEST, SelfTest::Off).await?;
+        self.set_gyro_range(gyro_range).await?;
+        self.set_accel_range(accel_range).await?;
+
+        let mut trim = [0u8; 4];
+        self.get_bytes(self.imu_addr, SELF_TEST_X, &mut trim).await?;
+
+        let mut response = [0.0f32; 6];
+        for i in 0..6 {
+            response[i] = self_test[i] - normal[i];
+        }
+
+        // drop everything sampled during the test, including DMP packets
+        self.drop_buffered_samples().await?;
+
+        let report = MpuSelfTest::new(trim, response);
+        self.mpu_self_test = Some(report);
+        Ok(report)
+    }
+
+    pub fn mpu_self_test(&self) -> Option<MpuSelfTest> {
+        self.mpu_self_test
+    }
+
+    /// Measures the HMC5883L internal bias field in both directions, leaves the magnetometer
+    /// configured for normal operation afterwards
+    pub async fn run_mag_self_test(&mut self) -> Result<MagSelfTest, BusError> {
+        let (positive, negative) = Hmc5883lDriver.self_test(&mut self.i2c, self.config.mag_rate).await?;
+        let report = MagSelfTest::new(positive, negative);
+        self.mag_self_test = Some(report);
+        Ok(report)
+    }
+
+    pub fn mag_self_test(&self) -> Option<MagSelfTest> {
+        self.mag_self_test
+    }
+
+    /// Raw accel and gyro in the sensor frame, without the gyro bias, temperature model,
+    /// accel calibration or mounting, see `get_accel_gyro_calibrated`
+    pub async fn get_accel_gyro(&mut self) -> Result<AccelGyro, BusError> {
+        let mut rx_buffer = [0u8; ACCEL_GYRO_PACKET];
+        let result = self.get_bytes(self.imu_addr, self.chip.accel_gyro_reg(), &mut rx_buffer).await;
+        let rx_buffer = self.with_secondary(result.map(|_| rx_buffer)).await?;
+        self.decode_accel_gyro(&rx_buffer).await
+    }
+
+    /// Accel and gyro with every calibration applied, in the tracker frame like the samples
+    /// fed to fusion
+    pub async fn get_accel_gyro_calibrated(&mut self) -> Result<AccelGyro, BusError> {
+        let accel_gyro = self.get_accel_gyro().await?;
+        Ok(self.compensate(accel_gyro))
+    }
+
+    /// Combines a sample of the first MPU6050 with one of the second, a failed read of
+    /// either is covered by the other
+    async fn with_secondary(&mut self, primary: Result<[u8; ACCEL_GYRO_PACKET], BusError>) -> Result<[u8; ACCEL_GYRO_PACKET], BusError> {
+        if self.redundancy.is_none() {
+            return primary;
+        }
+
+        let mut rx_buffer = [0u8; ACCEL_GYRO_PACKET];
+        let secondary = self.get_bytes(MPU6050_ADDR_AD0_HIGH, ACCEL_GYRO_READ, &mut rx_buffer).await.map(|_| rx_buffer);
+
+        let gyro_scale = self.gyro_range.full_scale();
+        let accel_scale = self.accel_range.full_scale();
+        let now = Instant::now();
+        let combined = self.redundancy.as_mut().and_then(|redundancy| {
+            redundancy.combine([primary.as_ref().ok(), secondary.as_ref().ok()], gyro_scale, accel_scale, now)
+        });
+        match combined {
+            Some(sample) => Ok(sample),
+            None => primary,
+        }
+    }
+
+    async fn decode_accel_gyro(&mut self, rx_buffer: &[u8]) -> Result<AccelGyro, BusError> {
+        let accel_scale = self.accel_range.full_scale();
+        let gyro_scale = self.gyro_range.full_scale();
+        let (accel_at, temp_at, gyro_at) = self.chip.layout();
+        let read = |at: usize| i16::from_be_bytes(rx_buffer[at..at + 2].try_into().unwrap());
+
+        let raw_gyro = [read(gyro_at), read(gyro_at + 2), read(gyro_at + 4)];
+        let mut gyro = Vector3::new(
+            convert_gyro(raw_gyro[0], gyro_scale),
+            convert_gyro(raw_gyro[1], gyro_scale),
+            convert_gyro(raw_gyro[2], gyro_scale),
+        );
+
+        // right after a range switch the output registers may still hold samples
+        // taken at the old range, so hold the last good reading until they settle
+        match self.gyro_settle_until {
+            Some(until) if Instant::now() < until => gyro = self.last_gyro,
+            _ => {
+                self.gyro_settle_until = None;
+                self.last_gyro = gyro;
+                if self.config.auto_gyro_range {
+                    self.adjust_gyro_range(&raw_gyro).await?;
+                }
+            }
+        }
+
+        let accel = Vector3::new(
+            convert_accel(read(accel_at), accel_scale),
+            convert_accel(read(accel_at + 2), accel_scale),
+            convert_accel(read(accel_at + 4), accel_scale),
+        );
+
         Ok(
-            MovementData {
-                pitch: euler.pitch as f64,
-                roll: euler.roll as f64,
-                yaw: euler.yaw as f64,
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
+            AccelGyro {
+                accel,
+                gyro,
+                temp: self.chip.temp(read(temp_at)),
             }
         )
     }
 
-    fn mpu_init(&mut self) -> Result<(), BusError> {
-        // set master mode enable
-        self.write_bit(MPU6050_ADDR, MASTER_MODE_ENABLE, MASTER_MODE_ENABLE_BIT, false)?;
+    /// Returns `None` until the magnetometer finished a new measurement, the reading is in gauss
+    pub async fn get_mag(&mut self) -> Result<Option<Vec3>, BusError> {
+        let mut rx_buffer = [0u8; MAX_MAG_PACKET];
+        let rx_buffer = &mut rx_buffer[..self.mag.packet_size()];
+        if self.config.mag_via_aux {
+            self.get_bytes(self.imu_addr, EXT_SENS_DATA, rx_buffer).await?;
+            return Ok(self.decode_mag(rx_buffer));
+        }
+
+        let device = self.mag.addr();
+        let (status_reg, ready_bit) = self.mag.status();
+        let status = self.get_byte(device, status_reg).await?;
+        if status & (1 << ready_bit) == 0 {
+            return Ok(None);
+        }
 
-        // set i2c bypass
-        self.write_bit(MPU6050_ADDR, I2C_BYPASS_ENABLE, I2C_BYPASS_ENABLE_BIT, true)?;
+        self.get_bytes(device, self.mag.data_reg(), rx_buffer).await?;
+        Ok(self.decode_mag(rx_buffer))
+    }
+
+    /// Reads accel, gyro and the magnetometer copy from the MPU6050 I2C master in one burst
+    pub async fn get_accel_gyro_mag(&mut self) -> Result<(AccelGyro, Option<Vec3>), BusError> {
+        let mut rx_buffer = [0u8; ACCEL_GYRO_PACKET + MAX_MAG_PACKET];
+        let rx_buffer = &mut rx_buffer[..ACCEL_GYRO_PACKET + self.mag.packet_size()];
+        let result = self.get_bytes(self.imu_addr, ACCEL_GYRO_READ, rx_buffer).await;
+        let accel_gyro = self.with_secondary(result.map(|_| rx_buffer[..ACCEL_GYRO_PACKET].try_into().unwrap())).await?;
+        let accel_gyro = self.decode_accel_gyro(&accel_gyro).await?;
 
-        // enable sleep
-        self.write_bit(MPU6050_ADDR, POWER_MGMT_1, SLEEP_ENABLED_BIT, false)?;
+        // the magnetometer copy only comes with the first MPU6050
+        let mag = match result {
+            Ok(_) => self.decode_mag(&rx_buffer[ACCEL_GYRO_PACKET..]),
+            Err(_) => None,
+        };
+        Ok((accel_gyro, mag))
+    }
 
-        // set clock source
-        self.write_bits(MPU6050_ADDR, POWER_MGMT_1, CLOCK_SELECT_BIT, CLOCK_SELECT_LENGTH, CLOCK_SOURCE)?;
+    fn decode_mag(&mut self, rx_buffer: &[u8]) -> Option<Vec3> {
+        let raw = self.mag.decode(rx_buffer);
 
-        // set full scale gyro
-        self.write_bits(MPU6050_ADDR, GYRO_CONFIG, GYRO_CONFIG_SELECT_BIT, GYRO_CONFIG_SELECT_LENGTH, GYRO_CONFIG_250)?;
+        // RDY stays set after a read until the next measurement lands, so the exact same
+        // values again mean nothing new arrived, unless a whole measurement period went by
+        // and the field simply didn't change
+        let now = Instant::now();
+        let period_passed = matches!(self.last_mag_at, Some(at) if now - at >= self.config.mag_rate.period());
+        if self.last_mag_raw == Some(raw) && !period_passed {
+            return None;
+        }
+        self.last_mag_raw = Some(raw);
+        self.last_mag_at = Some(now);
 
-        // set full scale accelerometer
-        self.write_bits(MPU6050_ADDR, ACCEL_CONFIG, ACCEL_CONFIG_SELECT_BIT, ACCEL_CONFIG_SELECT_LENGTH, ACCEL_CONFIG_2G)?;
+        if self.mag == Magnetometer::Hmc5883l && !self.validator.check_hmc(&raw) {
+            return None;
+        }
 
-        // enable sleep
-        self.write_bit(MPU6050_ADDR, POWER_MGMT_1, SLEEP_ENABLED_BIT, false)?;
+        Some(Vector3::new(raw[0] as f32, raw[1] as f32, raw[2] as f32).component_mul(&self.mag_sensitivity).component_mul(&self.mag_gain) / self.mag.lsb_per_gauss())
+    }
 
-        Ok(())
+    /// Adds a sample to the gyro temperature model, only feed it while the tracker is still
+    pub fn learn_gyro_temp_bias(&mut self, accel_gyro: &AccelGyro) {
+        self.gyro_temp_learner.add(accel_gyro.temp, &accel_gyro.gyro);
     }
 
-    fn hmc_init(&mut self) -> Result<(), BusError> {
-        // magnometer config
-        self.i2c.blocking_write(HMC5883L_ADDR, &[HMC5883L_CONFIG_A,
-            (HMC5883L_AVERAGING_8 << (HMC5883L_CRA_AVERAGE_BIT - HMC5883L_CRA_AVERAGE_LENGTH + 1)) |
-            (HMC5883L_RATE_15     << (HMC5883L_CRA_RATE_BIT - HMC5883L_CRA_RATE_LENGTH + 1)) |
-            (HMC5883L_BIAS_NORMAL << (HMC5883L_CRA_BIAS_BIT - HMC5883L_CRA_BIAS_LENGTH + 1))]).map_err(|_| BusError::BusWrite)?;
+    /// Fits the gyro temperature model from the learned samples and starts applying it.
+    /// The model covers the whole offset, so the gyro bias is cleared, see `gyro_calibrated`
+    pub fn fit_gyro_temp_model(&mut self) -> Option<GyroTempModel> {
+        if let Some(model) = self.gyro_temp_learner.fit() {
+            self.gyro_temp_model = Some(model);
+            self.clear_gyro_bias();
+        }
+        self.gyro_temp_model
+    }
 
-        // set gain
-        self.i2c.blocking_write(HMC5883L_ADDR, &[HMC5883L_CONFIG_B, HMC5883L_GAIN << (HMC5883L_GAIN_BIT - HMC5883L_GAIN_LENGTH + 1)]).map_err(|_| BusError::BusWrite)?;
-        
-        // set mode
-        self.i2c.blocking_write(HMC5883L_ADDR, &[HMC5883L_MODE_REG, HMC5883L_MODE_SINGLE << (HMC5883L_MODE_REG_BIT - HMC5883L_MODE_REG_LENGTH + 1)]).map_err(|_| BusError::BusWrite)?;
+    /// Replaces the gyro temperature model, e.g. with one stored from an earlier session.
+    /// Clears the gyro bias like `fit_gyro_temp_model`
+    pub fn set_gyro_temp_model(&mut self, model: Option<GyroTempModel>) {
+        self.gyro_temp_model = model;
+        self.gyro_temp_learner.reset();
+        self.clear_gyro_bias();
+    }
 
-        Ok(())
+    /// The bias was measured against the previous model, keeping it would subtract the
+    /// offset twice
+    fn clear_gyro_bias(&mut self) {
+        self.gyro_bias = Vector3::zeros();
+        self.gyro_calibrated = false;
     }
 
-    fn write_bit(&mut self, device: u8, addr: u8, bit: u8, enable: bool) -> Result<(), BusError> {
-        let mut data = self.get_byte(device, addr)?;
+    pub fn gyro_temp_model(&self) -> Option<GyroTempModel> {
+        self.gyro_temp_model
+    }
 
-        data = if enable {
-            data | (1 << bit)
-        } else {
-            data & !(1 << bit)
-        };
+    /// Waits for the tracker to hold still and averages the gyro over
+    /// `gyro_calibration_samples`, the result is subtracted from every sample from then on
+    pub async fn calibrate_gyro(&mut self) -> Result<Vec3, Gy87Error> {
+        let result = self.collect_gyro_bias().await;
+        self.drop_buffered_samples().await.map_err(|e| Gy87Error::BusError(e))?;
+        let bias = result?;
+        self.gyro_bias = bias;
+        self.gyro_calibrated = true;
+        Ok(bias)
+    }
 
-        self.i2c.blocking_write(device, &[addr, data]).map_err(|_| BusError::BusWrite)?;
-        Ok(())
+    async fn collect_gyro_bias(&mut self) -> Result<Vec3, Gy87Error> {
+        let period = self.sample_interval();
+        let mut calibrator = GyroCalibrator::new(self.config.gyro_calibration_samples);
+        let mut attempts = 0;
+        loop {
+            let mut accel_gyro = self.get_accel_gyro().await.map_err(|e| Gy87Error::BusError(e))?;
+            // only the offset left after the temperature model is measured
+            if let Some(model) = &self.gyro_temp_model {
+                accel_gyro.gyro -= model.bias(accel_gyro.temp);
+            }
+
+            match calibrator.add(&accel_gyro) {
+                GyroCalibrationStep::Collecting => {}
+                GyroCalibrationStep::Moved => {
+                    attempts += 1;
+                    if attempts >= GYRO_CALIBRATION_ATTEMPTS {
+                        return Err(Gy87Error::GyroNotStill);
+                    }
+                }
+                GyroCalibrationStep::Done(bias) => return Ok(bias),
+            }
+            Timer::after(period).await;
+        }
     }
 
-    fn write_bits(&mut self, device: u8, addr: u8, start_bit: u8, length: u8, mut data: u8) -> Result<(), BusError> {
-        let mut new_data = self.get_byte(device, addr)?;
-        let mask = ((1 << length) - 1) << (start_bit - length + 1);
-        data <<= start_bit - length + 1;
-        data &= mask;
-        new_data &= !mask;
-        new_data |= data;
+    /// Whether `calibrate_gyro` succeeded since power up or the last temperature model
+    /// change. `start` carries on with the zero
+    /// or stored bias when the tracker kept moving, ask the user to hold still and call
+    /// `calibrate_gyro` again in that case
+    pub fn gyro_calibrated(&self) -> bool {
+        self.gyro_calibrated
+    }
 
-        self.i2c.blocking_write(device, &[addr, new_data]).map_err(|_| BusError::BusWrite)?;
-        Ok(())
+    pub fn gyro_bias(&self) -> Vec3 {
+        self.gyro_bias
     }
 
-    fn get_byte(&mut self, device: u8, addr: u8) -> Result<u8, BusError> {
-        let mut rx_buffer: [u8; 1] = [0; 1];
-        self.i2c.blocking_write_read(device, &[addr], &mut rx_buffer).map_err(|_| BusError::BusReadWrite)?;
-        Ok(rx_buffer[0])
+    /// Replaces the gyro bias, e.g. with one stored from an earlier session
+    pub fn set_gyro_bias(&mut self, bias: Vec3) {
+        self.gyro_bias = bias;
     }
 
-    fn get_bytes(&mut self, device: u8, addr: u8, buffer: &mut [u8]) -> Result<(), BusError> {
-        self.i2c.blocking_write_read(device, &[addr], buffer).map_err(|_| BusError::BusReadWrite)?;
-        Ok(())
+    /// Collects `samples` uncalibrated accel readings for one face of the six position
+    /// calibration. Prompt the user to rest the tracker on each face in turn, a face that
+    /// fails has to be collected again before `AccelCalibrator::solve` succeeds
+    pub async fn collect_accel_face(&mut self, calibrator: &mut AccelCalibrator, face: Face, samples: u16) -> Result<(), Gy87Error> {
+        let period = self.sample_interval();
+        calibrator.clear(face);
+
+        let mut result = Ok(());
+        for _ in 0..samples {
+            match self.get_accel_gyro().await {
+                Ok(accel_gyro) => calibrator.add(face, &accel_gyro.accel),
+                Err(e) => {
+                    result = Err(Gy87Error::BusError(e));
+                    break;
+                }
+            }
+            Timer::after(period).await;
+        }
+        self.drop_buffered_samples().await.map_err(|e| Gy87Error::BusError(e))?;
+        result?;
+
+        calibrator.check(face).map_err(|e| {
+            calibrator.clear(face);
+            Gy87Error::AccelCalibration(face, e)
+        })
+    }
+
+    pub fn accel_calibration(&self) -> Option<AccelCalibration> {
+        self.accel_calibration
+    }
+
+    /// Starts applying an accel calibration from `AccelCalibrator::solve` or an earlier session
+    pub fn set_accel_calibration(&mut self, calibration: Option<AccelCalibration>) {
+        self.accel_calibration = calibration;
+    }
+
+    /// Filtered altitude in meters relative to where the tracker was started or last zeroed,
+    /// `None` until the BMP180 delivered enough samples
+    pub fn altitude(&self) -> Option<f32> {
+        self.baro.as_ref()?.barometer()?.altitude()
+    }
+
+    /// Compensated BMP180 pressure in Pa
+    pub fn pressure(&self) -> Option<f32> {
+        self.baro.as_ref()?.barometer()?.pressure()
+    }
+
+    /// BMP180 temperature in °C
+    pub fn baro_temp(&self) -> Option<f32> {
+        self.baro.as_ref()?.barometer()?.temp()
+    }
+
+    /// Makes the current height the zero of `altitude`, e.g. once the user stands up straight
+    pub fn zero_altitude(&mut self) {
+        if let Some(baro) = &mut self.baro {
+            baro.zero();
+        }
+    }
+
+    /// Waits for the data ready interrupt on `int_pin`, which has to be wired to the MPU6050 INT pin
+    pub async fn wait_for_sample<P: Wait>(&mut self, int_pin: &mut P) -> Result<(), Gy87Error> {
+        if !self.config.data_ready_interrupt {
+            return Err(Gy87Error::InvalidConfig(ConfigError::DataReadyInterruptDisabled));
+        }
+
+        // the interrupt is latched until the next read, so a sample that became ready
+        // while we were busy elsewhere is not missed. A sensor that lost power never
+        // raises it again, give up after a few samples so `update` can notice
+        let timeout = self.sample_interval() * INT_TIMEOUT_SAMPLES;
+        match with_timeout(timeout, int_pin.wait_for_high()).await {
+            Ok(result) => result.map_err(|_| Gy87Error::InterruptPin),
+            Err(_) => Err(Gy87Error::InterruptTimeout),
+        }
+    }
+
+    pub async fn update(&mut self, prev: &Instant) -> Result<MovementData, Gy87Error> {
+        let result = if self.config.dmp == DmpMode::Primary {
+            self.update_dmp().await
+        } else if self.config.fifo {
+            self.update_fifo().await
+        } else {
+            self.update_direct(prev).await
+        };
+
+        match result {
+            Ok(_) => {
+                self.consecutive_errors = 0;
+                self.recovered_errors = 0;
+            }
+            Err(e) if e.is_bus_error() => self.consecutive_errors = self.consecutive_errors.saturating_add(1),
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Bus errors in a row since the last successful `update`
+    pub fn consecutive_errors(&self) -> u16 {
+        self.consecutive_errors
+    }
+
+    /// Brings the sensors back after they lost power or the bus was reset, keeps the
+    /// fusion state and calibration
+    pub async fn reinit(&mut self) -> Result<(), Gy87Error> {
+        self.chip = self.detect_imu().await?;
+        self.imu_init().await.map_err(|e| Gy87Error::MpuInit(e))?;
+
+        self.mag = self.detect_mag().await?;
+        self.mag_init().await.map_err(|e| Gy87Error::HmcInit(e))?;
+        self.last_mag_raw = None;
+        self.last_mag_at = None;
+
+        if self.config.mag_via_aux {
+            self.aux_init().await.map_err(|e| Gy87Error::MpuInit(e))?;
+        }
+
+        if self.config.dmp != DmpMode::Off {
+            // the firmware lives in RAM and is gone after a power loss
+            self.dmp_init().await?;
+        }
+
+        self.validator.reset();
+        if let Some(redundancy) = &mut self.redundancy {
+            redundancy.reset();
+            // a second IMU that doesn't come back is dropped by the redundancy check,
+            // that's what it is there for
+            let _ = self.secondary_init().await;
+        }
+
+        if let Some(baro) = &mut self.baro {
+            baro.abort();
+        }
+
+        self.gyro_calm_samples = 0;
+        self.gyro_settle_until = None;
+        self.consecutive_errors = 0;
+        self.recovered_errors = 0;
+        Ok(())
+    }
+
+    async fn update_direct(&mut self, prev: &Instant) -> Result<MovementData, Gy87Error> {
+        let (accel_gyro, mag) = if self.config.mag_via_aux {
+            self.get_accel_gyro_mag().await.map_err(|e| Gy87Error::UpdateGetAccelGyro(e))?
+        } else {
+            let accel_gyro = self.get_accel_gyro().await.map_err(|e| Gy87Error::UpdateGetAccelGyro(e))?;
+            (accel_gyro, self.get_mag().await.map_err(|e| Gy87Error::UpdateMag(e))?)
+        };
+        let accel_gyro = self.validator.check(accel_gyro, Instant::now())?;
+        let accel_gyro = self.compensate(accel_gyro);
+        // a zero magnetometer vector makes the filter skip the magnetometer correction
+        let mag = mag.map(|mag| self.compensate_mag(mag)).unwrap_or_else(Vector3::zeros);
+        self.imu.update(accel_gyro.gyro, accel_gyro.accel, mag, prev.elapsed().as_micros() as f32 / 1000000.0);
+        if self.config.dmp == DmpMode::Compare {
+            self.poll_dmp().await?;
+        }
+        self.poll_baro().await.map_err(|e| Gy87Error::UpdateBaro(e))?;
+        Ok(self.movement_data())
+    }
+
+    async fn update_dmp(&mut self) -> Result<MovementData, Gy87Error> {
+        if !self.poll_dmp().await? {
+            return Err(Gy87Error::FifoEmpty);
+        }
+        self.poll_baro().await.map_err(|e| Gy87Error::UpdateBaro(e))?;
+        Ok(self.movement_data())
+    }
+
+    /// Drains the DMP packets from the FIFO and keeps the newest orientation, returns
+    /// whether there was one
+    async fn poll_dmp(&mut self) -> Result<bool, Gy87Error> {
+        let status = self.get_byte(self.imu_addr, INT_STATUS).await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+        let count = self.fifo_count().await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+
+        if FIFO_OVERFLOW.extract(status) == Some(true) || count >= self.chip.fifo_size() || count as usize % DMP_PACKET_SIZE != 0 {
+            self.reset_fifo().await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+            return Err(Gy87Error::FifoOverflow);
+        }
+
+        let packets = count as usize / DMP_PACKET_SIZE;
+        let mut rx_buffer = [0u8; DMP_PACKET_SIZE];
+        for _ in 0..packets {
+            self.get_bytes(self.imu_addr, FIFO_READ_WRITE, &mut rx_buffer).await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+        }
+        if packets > 0 {
+            let orientation = decode_quaternion(&rx_buffer);
+            self.dmp_orientation = Some(self.config.mounting.apply_orientation(&orientation));
+        }
+        Ok(packets > 0)
+    }
+
+    async fn update_fifo(&mut self) -> Result<MovementData, Gy87Error> {
+        let packet_size = self.fifo_packet_size();
+        let status = self.get_byte(self.imu_addr, INT_STATUS).await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+        let count = self.fifo_count().await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+
+        // once the FIFO wraps the packet boundaries are lost, start over
+        if FIFO_OVERFLOW.extract(status) == Some(true) || count >= self.chip.fifo_size() || count as usize % packet_size != 0 {
+            self.reset_fifo().await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+            return Err(Gy87Error::FifoOverflow);
+        }
+
+        let mut remaining = count as usize / packet_size;
+        if remaining == 0 {
+            return Err(Gy87Error::FifoEmpty);
+        }
+
+        // with the I2C master every packet carries its own magnetometer copy
+        let mag = if self.config.mag_via_aux {
+            None
+        } else {
+            self.get_mag().await.map_err(|e| Gy87Error::UpdateMag(e))?
+        };
+        let dt = self.sample_period();
+
+        let mut rx_buffer = [0u8; (ACCEL_GYRO_PACKET + MAX_MAG_PACKET) * FIFO_BURST_PACKETS];
+        while remaining > 0 {
+            let packets = remaining.min(FIFO_BURST_PACKETS);
+            let burst = &mut rx_buffer[..packets * packet_size];
+            self.get_bytes(self.imu_addr, FIFO_READ_WRITE, burst).await.map_err(|e| Gy87Error::UpdateFifo(e))?;
+
+            for (i, packet) in burst.chunks_exact(packet_size).enumerate() {
+                let accel_gyro = self.decode_accel_gyro(&packet[..ACCEL_GYRO_PACKET]).await.map_err(|e| Gy87Error::UpdateGetAccelGyro(e))?;
+                let accel_gyro = self.validator.check(accel_gyro, Instant::now())?;
+                let accel_gyro = self.compensate(accel_gyro);
+                let mag = if self.config.mag_via_aux {
+                    self.decode_mag(&packet[ACCEL_GYRO_PACKET..])
+                } else if packets == remaining && i == packets - 1 {
+                    // a new magnetometer reading goes with the most recent sample only
+                    mag
+                } else {
+                    None
+                };
+                let mag = mag.map(|mag| self.compensate_mag(mag));
+                self.imu.update(accel_gyro.gyro, accel_gyro.accel, mag.unwrap_or_else(Vector3::zeros), dt);
+            }
+            remaining -= packets;
+        }
+
+        self.poll_baro().await.map_err(|e| Gy87Error::UpdateBaro(e))?;
+        Ok(self.movement_data())
+    }
+
+    /// Corrections applied to every sample before it reaches the fusion filter
+    fn compensate(&self, mut accel_gyro: AccelGyro) -> AccelGyro {
+        if let Some(model) = &self.gyro_temp_model {
+            accel_gyro.gyro -= model.bias(accel_gyro.temp);
+        }
+        accel_gyro.gyro -= self.gyro_bias;
+        if let Some(calibration) = &self.accel_calibration {
+            accel_gyro.accel = calibration.apply(&accel_gyro.accel);
+        }
+
+        // biases are in the sensor frame, rotate afterwards
+        accel_gyro.accel = self.config.mounting.apply(&accel_gyro.accel);
+        accel_gyro.gyro = self.config.mounting.apply(&accel_gyro.gyro);
+        accel_gyro
+    }
+
+    /// Same frame as `compensate` leaves accel and gyro in
+    fn compensate_mag(&self, mag: Vec3) -> Vec3 {
+        self.config.mounting.apply(&mag)
+    }
+
+    fn fifo_packet_size(&self) -> usize {
+        if self.config.mag_via_aux {
+            ACCEL_GYRO_PACKET + self.mag.packet_size()
+        } else {
+            ACCEL_GYRO_PACKET
+        }
+    }
+
+    fn movement_data(&mut self) -> MovementData {
+        let (roll, pitch, yaw) = match self.dmp_orientation {
+            Some(orientation) if self.config.dmp == DmpMode::Primary => {
+                let (roll, pitch, yaw) = orientation.euler_angles();
+                (roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees())
+            }
+            _ => {
+                let euler = self.imu.get_euler();
+                (euler.roll, euler.pitch, euler.yaw)
+            }
+        };
+        let z = match self.altitude() {
+            Some(altitude) if self.config.altitude_as_z => altitude as f64,
+            _ => 0.0,
+        };
+        MovementData {
+            pitch: pitch as f64,
+            roll: roll as f64,
+            yaw: yaw as f64,
+            x: 0.0,
+            y: 0.0,
+            z,
+            quality: self.validator.take_quality(),
+        }
+    }
+
+    /// Collects a finished BMP180 conversion and starts the next one
+    async fn poll_baro(&mut self) -> Result<(), BusError> {
+        match &mut self.baro {
+            Some(baro) => baro.poll().await,
+            None => Ok(()),
+        }
+    }
+
+    async fn imu_init(&mut self) -> Result<(), BusError> {
+        self.chip.init(&mut self.i2c, self.imu_addr, &self.config).await?;
+        self.gyro_range = self.config.gyro_range;
+        self.accel_range = self.config.accel_range;
+
+        if self.config.fifo {
+            // select what goes into the fifo
+            self.write_register(fifo_sources()).await?;
+
+            self.reset_fifo().await?;
+
+            // enable fifo
+            self.modify(FIFO_ON, true).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Wakes the MPU6050 at 0x69 with the same filter, rate and ranges as the first one, its
+    /// INT pin and I2C master stay unused
+    async fn secondary_init(&mut self) -> Result<(), BusError> {
+        let config = Gy87Config {
+            gyro_range: self.gyro_range,
+            accel_range: self.accel_range,
+            data_ready_interrupt: false,
+            ..self.config
+        };
+        MpuDriver(Imu::Mpu6050).init(&mut self.i2c, MPU6050_ADDR_AD0_HIGH, &config).await
+    }
+
+    /// Mirrors a range change to the second MPU6050, if that write fails its samples
+    /// no longer agree and it gets dropped
+    async fn modify_secondary<R: Register, V: FieldValue>(&mut self, field: Field<R, V>, value: V) {
+        if self.redundancy.is_some() {
+            let _ = registers::modify(&mut self.i2c, MPU6050_ADDR_AD0_HIGH, field, value).await;
+        }
+    }
+
+    /// Uploads the DMP firmware and starts it, after `imu_init` set clock, filter and rate
+    async fn dmp_init(&mut self) -> Result<(), Gy87Error> {
+        let firmware = match self.config.dmp_firmware {
+            Some(firmware) => firmware,
+            None => return Err(Gy87Error::InvalidConfig(ConfigError::DmpWithoutFirmware)),
+        };
+
+        // the quaternion integration assumes these ranges
+        self.set_gyro_range(GyroRange::Dps2000).await.map_err(|e| Gy87Error::DmpInit(e))?;
+        self.set_accel_range(AccelRange::G2).await.map_err(|e| Gy87Error::DmpInit(e))?;
+
+        self.modify(DMP_ON, false).await.map_err(|e| Gy87Error::DmpInit(e))?;
+        if !self.write_dmp_memory(firmware.0).await.map_err(|e| Gy87Error::DmpInit(e))? {
+            return Err(Gy87Error::DmpFirmwareVerify);
+        }
+
+        // set program start address
+        let start = DMP_PROGRAM_START_ADDR.to_be_bytes();
+        self.i2c.write(self.imu_addr, &[DMP_PROGRAM_START, start[0], start[1]]).await.map_err(|_| Gy87Error::DmpInit(BusError::BusWrite))?;
+
+        if self.config.data_ready_interrupt && self.config.dmp == DmpMode::Primary {
+            // raise the int pin per DMP packet instead of per raw sample
+            self.write_register(RegisterValue::new().with(DMP_INT, true)).await.map_err(|e| Gy87Error::DmpInit(e))?;
+        }
+
+        // the DMP writes its packets into the fifo
+        self.modify(FIFO_ON, true).await.map_err(|e| Gy87Error::DmpInit(e))?;
+        self.modify(DMP_RESET, true).await.map_err(|e| Gy87Error::DmpInit(e))?;
+        self.reset_fifo().await.map_err(|e| Gy87Error::DmpInit(e))?;
+        self.modify(DMP_ON, true).await.map_err(|e| Gy87Error::DmpInit(e))?;
+
+        self.dmp_orientation = None;
+        Ok(())
+    }
+
+    /// Writes `data` to DMP memory from address 0 and reads every chunk back, returns
+    /// whether all of them matched
+    async fn write_dmp_memory(&mut self, data: &[u8]) -> Result<bool, BusError> {
+        let mut tx_buffer = [0u8; DMP_CHUNK_SIZE + 1];
+        let mut rx_buffer = [0u8; DMP_CHUNK_SIZE];
+        for (i, chunk) in data.chunks(DMP_CHUNK_SIZE).enumerate() {
+            // chunks never cross a bank since the chunk size divides the bank size
+            let addr = i * DMP_CHUNK_SIZE;
+            let bank = (addr / DMP_BANK_SIZE) as u8;
+            let start = (addr % DMP_BANK_SIZE) as u8;
+
+            self.i2c.write(self.imu_addr, &[DMP_BANK_SELECT, bank]).await.map_err(|_| BusError::BusWrite)?;
+            self.i2c.write(self.imu_addr, &[DMP_MEMORY_START, start]).await.map_err(|_| BusError::BusWrite)?;
+            tx_buffer[0] = DMP_MEMORY_READ_WRITE;
+            tx_buffer[1..chunk.len() + 1].copy_from_slice(chunk);
+            self.i2c.write(self.imu_addr, &tx_buffer[..chunk.len() + 1]).await.map_err(|_| BusError::BusWrite)?;
+
+            self.i2c.write(self.imu_addr, &[DMP_BANK_SELECT, bank]).await.map_err(|_| BusError::BusWrite)?;
+            self.i2c.write(self.imu_addr, &[DMP_MEMORY_START, start]).await.map_err(|_| BusError::BusWrite)?;
+            self.get_bytes(self.imu_addr, DMP_MEMORY_READ_WRITE, &mut rx_buffer[..chunk.len()]).await?;
+            if &rx_buffer[..chunk.len()] != chunk {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Hands the HMC5883L over to the MPU6050 I2C master, which then copies its data
+    /// registers into EXT_SENS_DATA on every sample
+    async fn aux_init(&mut self) -> Result<(), BusError> {
+        // read the magnetometer data registers through slave 0
+        // magnetometer addresses are 7 bit and packets at most 8 bytes, so both always fit
+        let device = Bits::new(self.mag.addr()).unwrap();
+        let length = Bits::new(self.mag.packet_size() as u8).unwrap();
+        self.write_register(RegisterValue::new().with(I2C_SLAVE0_READ, true).with(I2C_SLAVE0_DEVICE, device)).await?;
+        self.i2c.write(self.imu_addr, &[I2C_SLAVE0_REG, self.mag.data_reg()]).await.map_err(|_| BusError::BusWrite)?;
+        self.write_register(RegisterValue::new().with(I2C_SLAVE0_ENABLE, true).with(I2C_SLAVE0_LENGTH, length)).await?;
+
+        // set aux bus clock
+        self.write_register(RegisterValue::new().with(I2C_MASTER_CLOCK, I2cMasterClock::Khz400)).await?;
+
+        // clear i2c bypass
+        self.modify(I2C_BYPASS, false).await?;
+
+        // set master mode enable
+        self.modify(I2C_MASTER_MODE, true).await?;
+
+        if self.config.fifo {
+            // add the magnetometer copy to every fifo packet
+            self.write_register(fifo_sources().with(FIFO_SLAVE0, true)).await?;
+            self.reset_fifo().await?;
+        }
+
+        Ok(())
+    }
+
+    async fn fifo_count(&mut self) -> Result<u16, BusError> {
+        let mut rx_buffer = [0u8; 2];
+        self.get_bytes(self.imu_addr, FIFO_COUNT, &mut rx_buffer).await?;
+        Ok(u16::from_be_bytes(rx_buffer))
+    }
+
+    async fn reset_fifo(&mut self) -> Result<(), BusError> {
+        self.modify(FIFO_RESET, true).await
+    }
+
+    /// Drops what the FIFO buffered while samples were read directly, it overflows otherwise
+    async fn drop_buffered_samples(&mut self) -> Result<(), BusError> {
+        if self.config.fifo || self.config.dmp != DmpMode::Off {
+            self.reset_fifo().await?;
+        }
+        Ok(())
+    }
+
+    async fn mag_init(&mut self) -> Result<(), BusError> {
+        self.mag_sensitivity = self.mag.init(&mut self.i2c, self.config.mag_rate).await?;
+        Ok(())
+    }
+
+    async fn adjust_gyro_range(&mut self, raw_gyro: &[i16; 3]) -> Result<(), BusError> {
+        let peak = raw_gyro.iter().map(|v| v.unsigned_abs()).max().unwrap_or(0);
+
+        if peak >= GYRO_SATURATION_THRESHOLD {
+            self.gyro_calm_samples = 0;
+            if let Some(range) = self.gyro_range.step_up() {
+                self.switch_gyro_range(range).await?;
+            }
+            return Ok(());
+        }
+
+        let lower = match self.gyro_range.step_down() {
+            Some(lower) if lower.full_scale() >= self.config.gyro_range.full_scale() => lower,
+            _ => return Ok(()),
+        };
+
+        // threshold of the lower range expressed in raw units of the current range
+        let limit = GYRO_SATURATION_THRESHOLD as f32 * GYRO_STEP_DOWN_FRACTION * lower.full_scale() / self.gyro_range.full_scale();
+        if (peak as f32) < limit {
+            self.gyro_calm_samples += 1;
+            if self.gyro_calm_samples >= GYRO_STEP_DOWN_SAMPLES {
+                self.switch_gyro_range(lower).await?;
+            }
+        } else {
+            self.gyro_calm_samples = 0;
+        }
+
+        Ok(())
+    }
+
+    async fn switch_gyro_range(&mut self, range: GyroRange) -> Result<(), BusError> {
+        self.set_gyro_range(range).await?;
+        self.gyro_calm_samples = 0;
+        self.gyro_settle_until = Some(Instant::now() + GYRO_RANGE_SETTLE);
+        Ok(())
+    }
+
+    /// Average raw output ordered gyro x, y, z then accel x, y, z
+    async fn get_raw_average(&mut self, samples: u16) -> Result<[f32; 6], BusError> {
+        let period = self.sample_interval();
+        let mut sum = [0.0f32; 6];
+        let mut rx_buffer = [0u8; ACCEL_GYRO_PACKET];
+        for _ in 0..samples {
+            self.get_bytes(self.imu_addr, ACCEL_GYRO_READ, &mut rx_buffer).await?;
+            for (i, offset) in [8, 10, 12, 0, 2, 4].iter().enumerate() {
+                sum[i] += i16::from_be_bytes(rx_buffer[*offset..*offset + 2].try_into().unwrap()) as f32;
+            }
+            Timer::after(period).await;
+        }
+
+        for value in sum.iter_mut() {
+            *value /= samples as f32;
+        }
+        Ok(sum)
+    }
+
+    /// Read-modify-write of a single IMU register field
+    async fn modify<R: Register, V: FieldValue>(&mut self, field: Field<R, V>, value: V) -> Result<(), BusError> {
+        registers::modify(&mut self.i2c, self.imu_addr, field, value).await
+    }
+
+    async fn write_register<R: Register>(&mut self, value: RegisterValue<R>) -> Result<(), BusError> {
+        registers::write_register(&mut self.i2c, self.imu_addr, value).await
+    }
+
+    async fn get_byte(&mut self, device: u8, addr: u8) -> Result<u8, BusError> {
+        read_byte(&mut self.i2c, device, addr).await
+    }
+
+    async fn get_bytes(&mut self, device: u8, addr: u8, buffer: &mut [u8]) -> Result<(), BusError> {
+        read_bytes(&mut self.i2c, device, addr, buffer).await
+    }
+}
+
+impl<I: I2c + BusRecovery> Gy87<I> {
+    /// Takes the next step of the recovery policy once enough bus errors piled up, call it
+    /// after a failed `update` and report the returned step
+    pub async fn recover(&mut self) -> Result<Option<RecoveryStep>, Gy87Error> {
+        // errors that don't count leave the count where it was, its step was already taken.
+        // A step the bus skipped stays due, the furthest one due is taken
+        let policy = self.config.recovery;
+        let due = (self.recovered_errors + 1..=self.consecutive_errors).filter_map(|errors| policy.step(errors)).last();
+        let step = match due {
+            Some(step) => step,
+            None => {
+                self.recovered_errors = self.consecutive_errors;
+                return Ok(None);
+            }
+        };
+
+        let ran = match step {
+            RecoveryStep::Unstick => self.i2c.unstick(),
+            RecoveryStep::Recreate | RecoveryStep::Reinit => self.i2c.recreate(),
+        };
+        if !ran {
+            return Ok(None);
+        }
+        self.recovered_errors = self.consecutive_errors;
+
+        if step == RecoveryStep::Reinit {
+            self.reinit().await?;
+        }
+        Ok(Some(step))
+    }
+}
+
+/// Temperature, gyro and accel, keeps the packet layout of ACCEL_GYRO_READ
+fn fifo_sources() -> RegisterValue<FifoEnable> {
+    RegisterValue::new()
+        .with(FIFO_TEMP, true)
+        .with(FIFO_GYRO_X, true)
+        .with(FIFO_GYRO_Y, true)
+        .with(FIFO_GYRO_Z, true)
+        .with(FIFO_ACCEL, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use fusion_rs::nalgebra::Matrix3;
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::blocking::Blocking;
+    use crate::bmp180::{BaroOversampling, Conversion};
+    use crate::mock::MockI2c;
+    use crate::orientation::Axis;
+
+    /// MPU6050 and HMC5883L with their reset values
+    fn board() -> MockI2c {
+        let mut i2c = MockI2c::new();
+        i2c.device(MPU6050_ADDR)
+            .set(MPU6050_ADDR, MPU6050_WHOAMI_ADDR, &[MPU6050_WHOAMI])
+            .set(MPU6050_ADDR, POWER_MGMT_1, &[0x40]);
+        i2c.device(HMC5883L_ADDR)
+            .set(HMC5883L_ADDR, HMC5883L_CONFIG_A, &[0x10, 0x20, 0x01])
+            .set(HMC5883L_ADDR, HMC5883L_WHOAMI_ADDR, b"H43");
+        i2c
+    }
+
+    /// Nothing in `start` that waits on the sensors
+    fn config() -> Gy87Config {
+        Gy87Config {
+            gyro_range: GyroRange::Dps1000,
+            accel_range: AccelRange::G8,
+            dlpf: DlpfBandwidth::Hz21,
+            sample_rate: 100,
+            mag_rate: MagRate::Hz75,
+            mag_self_test: false,
+            mpu_self_test: false,
+            gyro_calibration_samples: 0,
+            ..Default::default()
+        }
+    }
+
+    fn expected_start_writes() -> Vec<(u8, Vec<u8>)> {
+        vec![
+            // master mode off, bypass on, awake, PLL clock
+            (MPU6050_ADDR, vec![USER_CONTROL, 0x00]),
+            (MPU6050_ADDR, vec![INT_PIN_CONFIG, 0x02]),
+            (MPU6050_ADDR, vec![POWER_MGMT_1, 0x00]),
+            (MPU6050_ADDR, vec![POWER_MGMT_1, 0x01]),
+            // DLPF 21 Hz, 1 kHz / (9 + 1), 1000 dps, 8g
+            (MPU6050_ADDR, vec![CONFIG, 0x04]),
+            (MPU6050_ADDR, vec![SAMPLE_RATE_DIVIDER, 9]),
+            (MPU6050_ADDR, vec![GYRO_CONFIG, 0x10]),
+            (MPU6050_ADDR, vec![ACCEL_CONFIG, 0x10]),
+            (MPU6050_ADDR, vec![POWER_MGMT_1, 0x01]),
+            // 8 sample average at 75 Hz, 1.3 Ga, continuous
+            (HMC5883L_ADDR, vec![HMC5883L_CONFIG_A, 0x78]),
+            (HMC5883L_ADDR, vec![HMC5883L_CONFIG_B, 0x20]),
+            (HMC5883L_ADDR, vec![HMC5883L_MODE_REG, 0x00]),
+        ]
+    }
+
+    #[test]
+    fn start_writes_mpu_and_hmc_setup() {
+        let mut gy87 = Gy87::new(board(), config());
+        block_on(gy87.start()).unwrap();
+
+        assert_eq!(gy87.imu(), Imu::Mpu6050);
+        assert_eq!(gy87.magnetometer(), Magnetometer::Hmc5883l);
+        assert_eq!(gy87.i2c.writes(), expected_start_writes().as_slice());
+    }
+
+    #[test]
+    fn start_on_blocking_bus() {
+        let mut gy87 = Gy87::new(Blocking(board()), config());
+        block_on(gy87.start()).unwrap();
+
+        assert_eq!(gy87.i2c.0.writes(), expected_start_writes().as_slice());
+    }
+
+    #[test]
+    fn start_without_imu_fails() {
+        let mut i2c = board();
+        i2c.set(MPU6050_ADDR, MPU6050_WHOAMI_ADDR, &[0x00]);
+        let mut gy87 = Gy87::new(i2c, config());
+
+        assert_eq!(block_on(gy87.start()), Err(Gy87Error::UnknownMPUDeviceAddr(0x00)));
+        assert!(gy87.i2c.writes().is_empty());
+    }
+
+    #[test]
+    fn finds_the_icm_with_ad0_high() {
+        let mut i2c = MockI2c::new();
+        i2c.device(MPU6050_ADDR_AD0_HIGH)
+            .set(MPU6050_ADDR_AD0_HIGH, ICM20948_WHOAMI_ADDR, &[ICM20948_WHOAMI]);
+        i2c.device(HMC5883L_ADDR)
+            .set(HMC5883L_ADDR, HMC5883L_WHOAMI_ADDR, b"H43");
+        let mut gy87 = Gy87::new(i2c, config());
+        block_on(gy87.start()).unwrap();
+
+        assert_eq!(gy87.imu(), Imu::Icm20948);
+        let imu_writes = gy87.i2c.writes().iter().filter(|(addr, _)| *addr != HMC5883L_ADDR);
+        assert!(imu_writes.clone().count() > 0);
+        assert!(imu_writes.clone().all(|(addr, _)| *addr == MPU6050_ADDR_AD0_HIGH));
+        // left in bank 0 with the configured gyro range in bank 2
+        let bank_writes = imu_writes.filter(|(_, bytes)| bytes[0] == ICM20948_BANK_SELECT);
+        assert_eq!(bank_writes.last().map(|(_, bytes)| bytes[1]), Some(0x00));
+    }
+
+    #[test]
+    fn mag_only_returns_new_measurements() {
+        let mut gy87 = Gy87::new(board(), config());
+        block_on(gy87.start()).unwrap();
+
+        // RDY clear
+        assert_eq!(block_on(gy87.get_mag()), Ok(None));
+
+        // x 100, z 300, y -200
+        gy87.i2c.set(HMC5883L_ADDR, MAG_READ, &[0x00, 0x64, 0x01, 0x2C, 0xFF, 0x38]);
+        gy87.i2c.set(HMC5883L_ADDR, HMC5883L_STATUS_REG, &[0x01]);
+        let mag = block_on(gy87.get_mag()).unwrap().unwrap();
+        let raw = mag * Magnetometer::Hmc5883l.lsb_per_gauss();
+        assert!((raw - Vector3::new(100.0, -200.0, 300.0)).norm() < 1e-3);
+
+        // RDY stays set, the same values right away are the same measurement
+        assert_eq!(block_on(gy87.get_mag()), Ok(None));
+
+        gy87.i2c.set(HMC5883L_ADDR, MAG_READ, &[0x00, 0x65]);
+        assert!(block_on(gy87.get_mag()).unwrap().is_some());
+
+        // a measurement period later they are a new measurement of an unchanged field
+        std::thread::sleep(std::time::Duration::from_micros(MagRate::Hz75.period().as_micros() + 1000));
+        assert!(block_on(gy87.get_mag()).unwrap().is_some());
+        assert_eq!(block_on(gy87.get_mag()), Ok(None));
+    }
+
+    #[test]
+    fn recovery_steps_once_per_bus_error() {
+        let config = Gy87Config {
+            fifo: true,
+            ..config()
+        };
+        let mut gy87 = Gy87::new(board(), config);
+        block_on(gy87.start()).unwrap();
+
+        gy87.i2c.fail = true;
+        for _ in 0..3 {
+            assert_eq!(block_on(gy87.update(&Instant::now())), Err(Gy87Error::UpdateFifo(BusError::BusReadWrite)));
+        }
+        assert_eq!(block_on(gy87.recover()), Ok(Some(RecoveryStep::Unstick)));
+        assert_eq!(block_on(gy87.recover()), Ok(None));
+
+        // an empty FIFO isn't a bus error and must not repeat the step
+        gy87.i2c.fail = false;
+        assert_eq!(block_on(gy87.update(&Instant::now())), Err(Gy87Error::FifoEmpty));
+        assert_eq!(gy87.consecutive_errors(), 3);
+        assert_eq!(block_on(gy87.recover()), Ok(None));
+        assert_eq!(gy87.i2c.unsticks, 1);
+
+        gy87.i2c.fail = true;
+        for _ in 0..3 {
+            assert!(block_on(gy87.update(&Instant::now())).is_err());
+            block_on(gy87.recover()).unwrap();
+        }
+        assert_eq!(gy87.i2c.unsticks, 1);
+        assert_eq!(gy87.i2c.recreates, 1);
+    }
+
+    #[test]
+    fn skipped_recovery_steps_stay_due() {
+        let config = Gy87Config {
+            fifo: true,
+            ..config()
+        };
+        let mut gy87 = Gy87::new(board(), config);
+        block_on(gy87.start()).unwrap();
+
+        gy87.i2c.fail = true;
+        gy87.i2c.busy = true;
+        for _ in 0..3 {
+            assert!(block_on(gy87.update(&Instant::now())).is_err());
+            assert_eq!(block_on(gy87.recover()), Ok(None));
+        }
+        assert_eq!(gy87.i2c.unsticks, 0);
+
+        // the unstick due at the third error runs once the bus is free
+        gy87.i2c.busy = false;
+        assert!(block_on(gy87.update(&Instant::now())).is_err());
+        assert_eq!(block_on(gy87.recover()), Ok(Some(RecoveryStep::Unstick)));
+        assert_eq!(block_on(gy87.recover()), Ok(None));
+        assert_eq!(gy87.i2c.unsticks, 1);
+    }
+
+    #[test]
+    fn mounting_keeps_accel_gyro_and_mag_aligned() {
+        let compensated = |mounting: Mounting, accel: Vec3, gyro: Vec3, mag: Vec3| {
+            let gy87 = Gy87::new(MockI2c::new(), Gy87Config {
+                mounting,
+                ..config()
+            });
+            let accel_gyro = gy87.compensate(AccelGyro {
+                accel,
+                gyro,
+                temp: 25.0,
+            });
+            (accel_gyro.accel, accel_gyro.gyro, gy87.compensate_mag(mag))
+        };
+        let close = |a: Vec3, b: Vec3| (a - b).norm() < 1e-5;
+
+        // upside down, gravity on the sensor -z and a counter clockwise yaw reads negative on the sensor z
+        let upside_down = Mounting::axis_aligned(Axis::PosX, Axis::NegY).unwrap();
+        let (accel, gyro, mag) = compensated(upside_down, Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.0, 0.0, -90.0), Vector3::new(0.2, 0.0, 0.4));
+        assert!(close(accel, Vector3::new(0.0, 0.0, 1.0)));
+        assert!(close(gyro, Vector3::new(0.0, 0.0, 90.0)));
+        assert!(close(mag, Vector3::new(0.2, 0.0, -0.4)));
+
+        // on its side with the sensor y up and the sensor z along the tracker x, a roll about the
+        // tracker x shows up on the sensor z
+        let on_side = Mounting::axis_aligned(Axis::PosZ, Axis::PosX).unwrap();
+        let (accel, gyro, mag) = compensated(on_side, Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 45.0), Vector3::new(0.0, -0.4, 0.2));
+        assert!(close(accel, Vector3::new(0.0, 0.0, 1.0)));
+        assert!(close(gyro, Vector3::new(45.0, 0.0, 0.0)));
+        assert!(close(mag, Vector3::new(0.2, 0.0, -0.4)));
+
+        // any mounting keeps gravity, field and rate right handed to each other, a mirrored gyro
+        // would turn the wrong way and fusion would diverge
+        let mut mountings = crate::orientation::tests::all_axis_aligned();
+        mountings.push(Mounting::from_euler(30.0, -45.0, 120.0));
+        let (sensor_accel, sensor_gyro, sensor_mag) = (Vector3::new(0.3, -0.5, 0.8), Vector3::new(-20.0, 70.0, 10.0), Vector3::new(0.1, 0.4, -0.2));
+        for mounting in mountings {
+            let (accel, gyro, mag) = compensated(mounting, sensor_accel, sensor_gyro, sensor_mag);
+            assert!((accel.norm() - sensor_accel.norm()).abs() < 1e-5);
+            assert!((gyro.dot(&accel) - sensor_gyro.dot(&sensor_accel)).abs() < 1e-3);
+            assert!((mag.dot(&accel) - sensor_mag.dot(&sensor_accel)).abs() < 1e-5);
+            let (_, handedness, _) = compensated(mounting, sensor_accel, sensor_accel.cross(&sensor_mag), sensor_mag);
+            assert!(close(handedness, accel.cross(&mag)));
+        }
+    }
+
+    #[test]
+    fn rejects_auto_gyro_range_with_fifo() {
+        let config = Gy87Config {
+            fifo: true,
+            auto_gyro_range: true,
+            ..config()
+        };
+        assert_eq!(config.validate(Imu::Mpu6050), Err(ConfigError::AutoGyroRangeWithFifo));
+
+        let config = Gy87Config {
+            fifo: false,
+            ..config
+        };
+        assert_eq!(config.validate(Imu::Mpu6050), Ok(()));
+    }
+
+    #[test]
+    fn altitude_needs_the_barometer() {
+        let config = Gy87Config {
+            altitude_as_z: true,
+            ..config()
+        };
+        let mut gy87 = Gy87::new(board(), config);
+        assert_eq!(block_on(gy87.start()), Err(Gy87Error::InvalidConfig(ConfigError::AltitudeWithoutBaro)));
+
+        let mut baro = MockI2c::new();
+        baro.device(BMP180_ADDR).set(BMP180_ADDR, BMP180_CHIP_ID_ADDR, &[BMP180_CHIP_ID]);
+        let mut gy87 = Gy87::new(board(), config).with_baro(Bmp180::new(baro, BaroOversampling::X8));
+        assert_eq!(block_on(gy87.start()), Ok(()));
+    }
+
+    #[test]
+    fn barometer_uses_its_own_bus_handle() {
+        let mut baro = MockI2c::new();
+        baro.device(BMP180_ADDR).set(BMP180_ADDR, BMP180_CHIP_ID_ADDR, &[BMP180_CHIP_ID]);
+        let mut gy87 = Gy87::new(board(), config()).with_baro(Bmp180::new(baro, BaroOversampling::X8));
+        block_on(gy87.start()).unwrap();
+        block_on(gy87.poll_baro()).unwrap();
+
+        // the board has nothing at the BMP180 address, so all of this went through its own handle
+        let baro = gy87.baro.as_ref().unwrap().barometer().unwrap();
+        assert_eq!(baro.pending(), Some(Conversion::Temperature));
+        assert!(gy87.i2c.writes().iter().all(|(addr, _)| *addr != BMP180_ADDR));
+    }
+
+    #[test]
+    fn icm_runs_at_the_closest_rate() {
+        let config = Gy87Config::default();
+        assert_eq!(config.validate(Imu::Icm20948), Ok(()));
+        // 1125 Hz / 6
+        assert_eq!(config.sample_rate_divider(Imu::Icm20948), 5);
+        assert_eq!(config.effective_sample_rate(Imu::Icm20948), 187.5);
+        assert_eq!(config.effective_sample_rate(Imu::Mpu6050), 200.0);
+
+        // 112.5 Hz is below twice the 119.5 Hz the ICM-20948 filter passes at this code
+        let config = Gy87Config {
+            dlpf: DlpfBandwidth::Hz94,
+            sample_rate: 110,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(Imu::Icm20948), Err(ConfigError::SampleRateBelowNyquist(110)));
+    }
+
+    #[test]
+    fn decodes_accel_gyro() {
+        let mut gy87 = Gy87::new(board(), config());
+        block_on(gy87.start()).unwrap();
+
+        // accel 1g, -2g, 0.5g at 8g, temperature, gyro 500, -250, 0.9765625 dps at 1000 dps
+        let mut sample = [0u8; ACCEL_GYRO_PACKET];
+        for (i, raw) in [4096i16, -8192, 2048, -521, 16384, -8192, 32].iter().enumerate() {
+            sample[i * 2..i * 2 + 2].copy_from_slice(&raw.to_be_bytes());
+        }
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ, &sample);
+
+        let accel_gyro = block_on(gy87.get_accel_gyro()).unwrap();
+        assert_eq!(accel_gyro.accel, Vector3::new(1.0, -2.0, 0.5));
+        assert_eq!(accel_gyro.gyro, Vector3::new(500.0, -250.0, 0.9765625));
+        assert!((accel_gyro.temp - (-521.0 / 340.0 + 36.53)).abs() < 1e-4);
+    }
+
+    /// Board whose gyro swings between two rates on every sample, like a tracker in hand
+    struct Shaking(MockI2c, bool);
+
+    impl embedded_hal_async::i2c::ErrorType for Shaking {
+        type Error = embedded_hal_async::i2c::ErrorKind;
+    }
+
+    impl I2c for Shaking {
+        async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+            self.0.read(address, read).await
+        }
+
+        async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+            self.0.write(address, write).await
+        }
+
+        async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+            if address == MPU6050_ADDR && write == [ACCEL_GYRO_READ] {
+                self.1 = !self.1;
+                let rate: i16 = if self.1 { 16384 } else { -16384 };
+                self.0.set(MPU6050_ADDR, ACCEL_GYRO_READ + 8, &rate.to_be_bytes());
+            }
+            self.0.write_read(address, write, read).await
+        }
+
+        async fn transaction(&mut self, address: u8, operations: &mut [embedded_hal_async::i2c::Operation<'_>]) -> Result<(), Self::Error> {
+            self.0.transaction(address, operations).await
+        }
+    }
+
+    #[test]
+    fn moving_during_gyro_calibration_keeps_the_bias() {
+        let config = Gy87Config {
+            gyro_calibration_samples: 2,
+            ..config()
+        };
+        let mut gy87 = Gy87::new(Shaking(board(), false), config);
+        let stored = Vector3::new(1.0, -2.0, 0.5);
+        gy87.set_gyro_bias(stored);
+
+        assert_eq!(block_on(gy87.start()), Ok(()));
+        assert!(!gy87.gyro_calibrated());
+        assert_eq!(gy87.gyro_bias(), stored);
+        assert_eq!(block_on(gy87.calibrate_gyro()), Err(Gy87Error::GyroNotStill));
+
+        let mut gy87 = Gy87::new(board(), config);
+        block_on(gy87.start()).unwrap();
+        assert!(gy87.gyro_calibrated());
+        assert_eq!(gy87.gyro_bias(), Vector3::zeros());
+    }
+
+    #[test]
+    fn temperature_model_replaces_the_calibrated_bias() {
+        // 10 °/s offset at a raw temperature of 0, drifting 2 °/s over 10 °C
+        let mut i2c = board();
+        i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 6, &[0, 0, 0x01, 0x48]);
+        let mut gy87 = Gy87::new(i2c, Gy87Config {
+            gyro_calibration_samples: 2,
+            ..config()
+        });
+        block_on(gy87.start()).unwrap();
+        assert!(gy87.gyro_calibrated());
+
+        for (temp, gyro) in [(0i16, 328i16), (3400, 394)] {
+            gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 6, &temp.to_be_bytes());
+            gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 8, &gyro.to_be_bytes());
+            let accel_gyro = block_on(gy87.get_accel_gyro()).unwrap();
+            gy87.learn_gyro_temp_bias(&accel_gyro);
+        }
+        assert!(gy87.fit_gyro_temp_model().is_some());
+        assert!(!gy87.gyro_calibrated());
+        assert_eq!(gy87.gyro_bias(), Vector3::zeros());
+
+        // still, in between the learned temperatures
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 6, &1700i16.to_be_bytes());
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 8, &361i16.to_be_bytes());
+        let accel_gyro = block_on(gy87.get_accel_gyro()).unwrap();
+        assert!(gy87.compensate(accel_gyro).gyro.norm() < 0.05);
+    }
+
+    #[test]
+    fn gyro_calibration_resets_the_fifo() {
+        let config = Gy87Config {
+            fifo: true,
+            gyro_calibration_samples: 2,
+            ..config()
+        };
+        let mut gy87 = Gy87::new(board(), config);
+        block_on(gy87.start()).unwrap();
+        gy87.i2c.clear_writes();
+
+        block_on(gy87.calibrate_gyro()).unwrap();
+        let (addr, write) = gy87.i2c.writes().last().unwrap();
+        assert_eq!(*addr, MPU6050_ADDR);
+        assert_eq!(write[0], USER_CONTROL);
+        assert_ne!(write[1] & FIFO_RESET.mask(), 0);
+    }
+
+    #[test]
+    fn calibrated_read_applies_the_accel_calibration() {
+        let mut gy87 = Gy87::new(board(), config());
+        block_on(gy87.start()).unwrap();
+        gy87.set_accel_calibration(Some(AccelCalibration {
+            offset: Vector3::new(0.0, 0.0, -0.25),
+            matrix: Matrix3::identity(),
+        }));
+        gy87.set_gyro_bias(Vector3::new(0.0, 0.0, 125.0));
+
+        // lying flat and still, the sensor reads 0.75 g and 125 °/s
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 4, &3072i16.to_be_bytes());
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ + 12, &4096i16.to_be_bytes());
+
+        let raw = block_on(gy87.get_accel_gyro()).unwrap();
+        assert!((raw.accel - Vector3::new(0.0, 0.0, 0.75)).norm() < 1e-3);
+        assert!((raw.gyro - Vector3::new(0.0, 0.0, 125.0)).norm() < 1e-3);
+
+        let calibrated = block_on(gy87.get_accel_gyro_calibrated()).unwrap();
+        assert!((calibrated.accel - Vector3::new(0.0, 0.0, 1.0)).norm() < 1e-3);
+        assert!(calibrated.gyro.norm() < 1e-3);
+    }
+
+    #[test]
+    fn calibrated_garbage_is_still_rejected() {
+        let mut gy87 = Gy87::new(board(), config());
+        block_on(gy87.start()).unwrap();
+        gy87.set_accel_calibration(Some(AccelCalibration {
+            offset: Vector3::new(0.03, -0.05, 0.02),
+            matrix: Matrix3::from_diagonal(&Vector3::new(1.02, 0.97, 1.01)),
+        }));
+
+        // a sensor that stopped answering reads all 0xFF
+        gy87.i2c.set(MPU6050_ADDR, ACCEL_GYRO_READ, &[0xFF; ACCEL_GYRO_PACKET]);
+        assert_eq!(block_on(gy87.update(&Instant::now())), Err(Gy87Error::InvalidSample));
     }
 }
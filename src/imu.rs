@@ -0,0 +1,252 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+
+use crate::constants::*;
+use crate::gy87::{AccelRange, BusError, Gy87Config, GyroRange};
+use crate::magnetometer::Magnetometer;
+use crate::registers::*;
+use crate::util::convert_temp;
+
+/// Time for the ICM-20948 to come back up after a reset
+const ICM_RESET_TIME: Duration = Duration::from_millis(10);
+
+/// Register setup of an accel and gyro chip at `addr`, 0x68 or 0x69 depending on AD0
+pub trait ImuDriver {
+    /// Wakes the chip and sets clock, filter, output rate, ranges and the data ready interrupt
+    /// from `config`, the magnetometer stays reachable through bypass
+    async fn init<I: I2c>(&self, i2c: &mut I, addr: u8, config: &Gy87Config) -> Result<(), BusError>;
+
+    async fn set_gyro_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: GyroRange) -> Result<(), BusError>;
+
+    async fn set_accel_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: AccelRange) -> Result<(), BusError>;
+}
+
+/// MPU6050, MPU6500 and MPU9250, which share the MPU6050 register map
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct MpuDriver(pub Imu);
+
+impl ImuDriver for MpuDriver {
+    async fn init<I: I2c>(&self, i2c: &mut I, addr: u8, config: &Gy87Config) -> Result<(), BusError> {
+        // master mode off, the magnetometer is reached through bypass
+        modify(i2c, addr, I2C_MASTER_MODE, false).await?;
+
+        // set i2c bypass
+        modify(i2c, addr, I2C_BYPASS, true).await?;
+
+        // wake up
+        modify(i2c, addr, SLEEP, false).await?;
+
+        // set clock source
+        modify(i2c, addr, CLOCK_SELECT, ClockSource::Pll).await?;
+
+        // set low pass filter
+        modify(i2c, addr, DLPF, config.dlpf).await?;
+        if self.0 != Imu::Mpu6050 {
+            // the MPU6500 filters the accelerometer separately
+            modify(i2c, addr, ACCEL_DLPF, config.dlpf).await?;
+        }
+
+        // set output data rate
+        write_byte(i2c, addr, SAMPLE_RATE_DIVIDER, config.sample_rate_divider(self.0)).await?;
+
+        // set full scale gyro
+        self.set_gyro_range(i2c, addr, config.gyro_range).await?;
+
+        // set full scale accelerometer
+        self.set_accel_range(i2c, addr, config.accel_range).await?;
+
+        // wake up
+        modify(i2c, addr, SLEEP, false).await?;
+
+        if config.data_ready_interrupt {
+            // latch int pin until any register is read
+            modify(i2c, addr, INT_LATCH, true).await?;
+            modify(i2c, addr, INT_READ_CLEAR, true).await?;
+
+            // enable data ready interrupt
+            write_register(i2c, addr, RegisterValue::new().with(DATA_READY_INT, true)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_gyro_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: GyroRange) -> Result<(), BusError> {
+        modify(i2c, addr, GYRO_FULL_SCALE, range).await
+    }
+
+    async fn set_accel_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: AccelRange) -> Result<(), BusError> {
+        modify(i2c, addr, ACCEL_FULL_SCALE, range).await
+    }
+}
+
+/// ICM-20948, its configuration registers live in bank 2 and every access leaves bank 0 selected
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Icm20948Driver;
+
+impl Icm20948Driver {
+    async fn bank<I: I2c>(&self, i2c: &mut I, addr: u8, bank: IcmBank) -> Result<(), BusError> {
+        write_register(i2c, addr, RegisterValue::new().with(ICM_BANK, bank)).await
+    }
+}
+
+impl ImuDriver for Icm20948Driver {
+    async fn init<I: I2c>(&self, i2c: &mut I, addr: u8, config: &Gy87Config) -> Result<(), BusError> {
+        self.bank(i2c, addr, IcmBank::Bank0).await?;
+
+        // reset
+        write_register(i2c, addr, RegisterValue::new().with(ICM_RESET, true)).await?;
+        Timer::after(ICM_RESET_TIME).await;
+
+        // wake up and select clock source
+        write_register(i2c, addr, RegisterValue::new().with(ICM_CLOCK_SELECT, ClockSource::Pll)).await?;
+
+        // enable accel and gyro
+        write_byte(i2c, addr, ICM20948_POWER_MGMT_2, ICM20948_ACCEL_GYRO_ON).await?;
+
+        // master mode off, the magnetometer is reached through bypass
+        modify(i2c, addr, ICM_I2C_MASTER_MODE, false).await?;
+
+        // set i2c bypass
+        modify(i2c, addr, ICM_I2C_BYPASS, true).await?;
+
+        self.bank(i2c, addr, IcmBank::Bank2).await?;
+
+        // set output data rate
+        let divider = config.sample_rate_divider(Imu::Icm20948);
+        write_byte(i2c, addr, ICM20948_GYRO_SAMPLE_RATE_DIVIDER, divider).await?;
+        write_byte(i2c, addr, ICM20948_ACCEL_SAMPLE_RATE_DIVIDER_2, divider).await?;
+
+        // set low pass filter
+        modify(i2c, addr, ICM_GYRO_DLPF, config.dlpf).await?;
+        modify(i2c, addr, ICM_GYRO_DLPF_ENABLE, true).await?;
+        modify(i2c, addr, ICM_ACCEL_DLPF, config.dlpf).await?;
+        modify(i2c, addr, ICM_ACCEL_DLPF_ENABLE, true).await?;
+
+        self.bank(i2c, addr, IcmBank::Bank0).await?;
+
+        // set full scale gyro
+        self.set_gyro_range(i2c, addr, config.gyro_range).await?;
+
+        // set full scale accelerometer
+        self.set_accel_range(i2c, addr, config.accel_range).await?;
+
+        if config.data_ready_interrupt {
+            // latch int pin until any register is read
+            modify(i2c, addr, ICM_INT_LATCH, true).await?;
+            modify(i2c, addr, ICM_INT_READ_CLEAR, true).await?;
+
+            // enable data ready interrupt
+            write_register(i2c, addr, RegisterValue::new().with(ICM_DATA_READY_INT, true)).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_gyro_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: GyroRange) -> Result<(), BusError> {
+        self.bank(i2c, addr, IcmBank::Bank2).await?;
+        modify(i2c, addr, ICM_GYRO_FULL_SCALE, range).await?;
+        self.bank(i2c, addr, IcmBank::Bank0).await
+    }
+
+    async fn set_accel_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: AccelRange) -> Result<(), BusError> {
+        self.bank(i2c, addr, IcmBank::Bank2).await?;
+        modify(i2c, addr, ICM_ACCEL_FULL_SCALE, range).await?;
+        self.bank(i2c, addr, IcmBank::Bank0).await
+    }
+}
+
+/// Accel and gyro chip, picked from its WHO_AM_I value in `Gy87::start`
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Imu {
+    Mpu6050,
+    /// MPU6500 and register compatible parts without a magnetometer
+    Mpu6500,
+    /// MPU9250 and MPU9255, an MPU6500 with an AK8963 magnetometer
+    Mpu9250,
+    /// ICM-20948 with an AK09916 magnetometer, uses banked registers
+    Icm20948,
+}
+
+impl Imu {
+    /// Addresses the IMU answers at, depending on AD0
+    pub const ADDRS: [u8; 2] = [MPU6050_ADDR, MPU6050_ADDR_AD0_HIGH];
+
+    /// Matches the MPU family WHO_AM_I register, the ICM-20948 is identified separately
+    pub fn from_who_am_i(who_am_i: u8) -> Option<Imu> {
+        match who_am_i {
+            MPU6050_WHOAMI => Some(Imu::Mpu6050),
+            MPU6500_WHOAMI => Some(Imu::Mpu6500),
+            MPU9250_WHOAMI | MPU9255_WHOAMI => Some(Imu::Mpu9250),
+            _ => None,
+        }
+    }
+
+    /// First register of the accel, temperature and gyro block
+    pub fn accel_gyro_reg(&self) -> u8 {
+        match self {
+            Imu::Icm20948 => ICM20948_ACCEL_GYRO_READ,
+            _ => ACCEL_GYRO_READ,
+        }
+    }
+
+    /// Offsets of accel, temperature and gyro inside the block
+    pub fn layout(&self) -> (usize, usize, usize) {
+        match self {
+            Imu::Icm20948 => (0, 12, 6),
+            _ => (0, 6, 8),
+        }
+    }
+
+    /// Die temperature in °C
+    pub fn temp(&self, raw: i16) -> f32 {
+        match self {
+            Imu::Mpu6050 => convert_temp(raw, 340.0, 36.53),
+            _ => convert_temp(raw, 333.87, 21.0),
+        }
+    }
+
+    pub fn fifo_size(&self) -> u16 {
+        match self {
+            Imu::Mpu6050 => FIFO_SIZE,
+            _ => MPU6500_FIFO_SIZE,
+        }
+    }
+
+    /// Magnetometer inside the package, reached through I2C bypass
+    pub fn internal_mag(&self) -> Option<Magnetometer> {
+        match self {
+            Imu::Mpu9250 => Some(Magnetometer::Ak8963),
+            Imu::Icm20948 => Some(Magnetometer::Ak09916),
+            _ => None,
+        }
+    }
+
+    /// Whether the MPU6050 FIFO and I2C master registers are available
+    pub fn mpu_compatible(&self) -> bool {
+        *self != Imu::Icm20948
+    }
+}
+
+// picks the driver of the detected chip
+impl ImuDriver for Imu {
+    async fn init<I: I2c>(&self, i2c: &mut I, addr: u8, config: &Gy87Config) -> Result<(), BusError> {
+        match self {
+            Imu::Icm20948 => Icm20948Driver.init(i2c, addr, config).await,
+            imu => MpuDriver(*imu).init(i2c, addr, config).await,
+        }
+    }
+
+    async fn set_gyro_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: GyroRange) -> Result<(), BusError> {
+        match self {
+            Imu::Icm20948 => Icm20948Driver.set_gyro_range(i2c, addr, range).await,
+            imu => MpuDriver(*imu).set_gyro_range(i2c, addr, range).await,
+        }
+    }
+
+    async fn set_accel_range<I: I2c>(&self, i2c: &mut I, addr: u8, range: AccelRange) -> Result<(), BusError> {
+        match self {
+            Imu::Icm20948 => Icm20948Driver.set_accel_range(i2c, addr, range).await,
+            imu => MpuDriver(*imu).set_accel_range(i2c, addr, range).await,
+        }
+    }
+}
@@ -0,0 +1,23 @@
+#![cfg_attr(not(test), no_std)]
+#![feature(async_fn_in_trait)]
+
+pub mod bmp180;
+pub mod blocking;
+pub mod bus;
+pub mod calibration;
+pub mod compensation;
+pub mod constants;
+pub mod diagnostics;
+pub mod dmp;
+pub mod gy87;
+pub mod imu;
+pub mod magnetometer;
+pub mod orientation;
+pub mod recovery;
+pub mod redundancy;
+pub mod registers;
+pub mod util;
+pub mod validation;
+
+#[cfg(test)]
+mod mock;
@@ -0,0 +1,276 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+use fusion_rs::nalgebra::Vector3;
+use fusion_rs::Vec3;
+
+use crate::constants::*;
+use crate::gy87::{BusError, MagRate};
+use crate::registers::*;
+
+/// Largest block read from a magnetometer for one sample
+pub const MAX_MAG_PACKET: usize = 8;
+
+/// Single measurement conversion time, with some margin
+const HMC_MEASUREMENT_TIME: Duration = Duration::from_millis(10);
+/// Power on time after a QMC5883L soft reset
+const QMC_RESET_TIME: Duration = Duration::from_millis(1);
+/// Time for the AK8963 and AK09916 to reset or switch modes
+const AK_MODE_SWITCH_TIME: Duration = Duration::from_millis(1);
+
+/// HMC5883L sensitivity at the default gain
+const HMC_LSB_PER_GAUSS: f32 = 1090.0;
+/// QMC5883L sensitivity in the 8 gauss range
+const QMC_LSB_PER_GAUSS: f32 = 3000.0;
+/// AK8963 and AK09916 sensitivity, 0.15 µT/LSB in 16 bit mode
+const AK_LSB_PER_GAUSS: f32 = 666.7;
+
+/// Magnetometer found on the board, GY-87 clones often carry a QMC5883L instead of the HMC5883L
+/// and 9-axis modules use the AK chip inside the IMU package
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Magnetometer {
+    Hmc5883l,
+    Qmc5883l,
+    Ak8963,
+    Ak09916,
+}
+
+impl Magnetometer {
+    pub fn addr(&self) -> u8 {
+        match self {
+            Magnetometer::Hmc5883l => HMC5883L_ADDR,
+            Magnetometer::Qmc5883l => QMC5883L_ADDR,
+            Magnetometer::Ak8963 => AK8963_ADDR,
+            Magnetometer::Ak09916 => AK09916_ADDR,
+        }
+    }
+
+    /// Register and expected value of a single byte chip id, the HMC5883L uses three bytes
+    pub fn chip_id(&self) -> Option<(u8, u8)> {
+        match self {
+            Magnetometer::Hmc5883l => None,
+            Magnetometer::Qmc5883l => Some((QMC5883L_CHIP_ID_ADDR, QMC5883L_CHIP_ID)),
+            Magnetometer::Ak8963 => Some((AK8963_WHOAMI_ADDR, AK8963_WHOAMI)),
+            Magnetometer::Ak09916 => Some((AK09916_WHOAMI_ADDR, AK09916_WHOAMI)),
+        }
+    }
+
+    /// Status register and its data ready bit
+    pub fn status(&self) -> (u8, u8) {
+        match self {
+            Magnetometer::Hmc5883l => (HMC5883L_STATUS_REG, HMC5883L_STATUS_READY_BIT),
+            Magnetometer::Qmc5883l => (QMC5883L_STATUS_REG, QMC5883L_STATUS_READY_BIT),
+            Magnetometer::Ak8963 => (AK8963_STATUS_1, AK8963_STATUS_READY_BIT),
+            Magnetometer::Ak09916 => (AK09916_STATUS_1, AK09916_STATUS_READY_BIT),
+        }
+    }
+
+    pub fn data_reg(&self) -> u8 {
+        match self {
+            Magnetometer::Hmc5883l => MAG_READ,
+            Magnetometer::Qmc5883l => QMC5883L_DATA,
+            Magnetometer::Ak8963 => AK8963_DATA,
+            Magnetometer::Ak09916 => AK09916_DATA,
+        }
+    }
+
+    /// Bytes read per sample, the AK chips only release the next measurement once ST2 was read
+    pub fn packet_size(&self) -> usize {
+        match self {
+            Magnetometer::Hmc5883l | Magnetometer::Qmc5883l => 6,
+            Magnetometer::Ak8963 => 7,
+            Magnetometer::Ak09916 => 8,
+        }
+    }
+
+    pub fn lsb_per_gauss(&self) -> f32 {
+        match self {
+            Magnetometer::Hmc5883l => HMC_LSB_PER_GAUSS,
+            Magnetometer::Qmc5883l => QMC_LSB_PER_GAUSS,
+            Magnetometer::Ak8963 | Magnetometer::Ak09916 => AK_LSB_PER_GAUSS,
+        }
+    }
+
+    /// Raw reading in x, y, z order of the accel and gyro axes
+    pub fn decode(&self, rx_buffer: &[u8]) -> [i16; 3] {
+        let be = |at: usize| i16::from_be_bytes(rx_buffer[at..at + 2].try_into().unwrap());
+        let le = |at: usize| i16::from_le_bytes(rx_buffer[at..at + 2].try_into().unwrap());
+
+        match self {
+            // data registers are ordered x, z, y
+            Magnetometer::Hmc5883l => [be(0), be(4), be(2)],
+            Magnetometer::Qmc5883l => [le(0), le(2), le(4)],
+            // x and y are swapped against the MPU9250 accel and gyro, z points the other way
+            Magnetometer::Ak8963 => [le(2), le(0), le(4).saturating_neg()],
+            // y and z point the other way than the ICM-20948 accel and gyro
+            Magnetometer::Ak09916 => [le(0), le(2).saturating_neg(), le(4).saturating_neg()],
+        }
+    }
+}
+
+/// Register setup of a magnetometer
+pub trait MagDriver {
+    /// Starts continuous measurements at `rate`, returns the factory sensitivity adjustment
+    /// per axis in the order of `Magnetometer::decode`
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError>;
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Hmc5883lDriver;
+
+impl Hmc5883lDriver {
+    /// Measures the internal bias field in the positive and then the negative direction at
+    /// the self test gain, leaves the magnetometer running at `rate` afterwards
+    pub async fn self_test<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<([i16; 3], [i16; 3]), BusError> {
+        write_register(i2c, HMC5883L_ADDR, RegisterValue::new().with(HMC_GAIN, HmcGain::Ga4_7)).await?;
+
+        let positive = self.biased_measurement(i2c, HmcBias::Positive).await?;
+        let negative = self.biased_measurement(i2c, HmcBias::Negative).await?;
+
+        self.init(i2c, rate).await?;
+        Ok((positive, negative))
+    }
+
+    async fn biased_measurement<I: I2c>(&self, i2c: &mut I, bias: HmcBias) -> Result<[i16; 3], BusError> {
+        write_register(i2c, HMC5883L_ADDR, RegisterValue::new()
+            .with(HMC_AVERAGING, HmcAveraging::Samples8)
+            .with(HMC_RATE, MagRate::Hz15)
+            .with(HMC_BIAS, bias)).await?;
+
+        // the first measurement after a gain change still uses the previous gain
+        let mut rx_buffer = [0u8; 6];
+        for _ in 0..2 {
+            write_register(i2c, HMC5883L_ADDR, RegisterValue::new().with(HMC_MODE, HmcMode::Single)).await?;
+            Timer::after(HMC_MEASUREMENT_TIME).await;
+            read_bytes(i2c, HMC5883L_ADDR, MAG_READ, &mut rx_buffer).await?;
+        }
+
+        Ok(Magnetometer::Hmc5883l.decode(&rx_buffer))
+    }
+}
+
+impl MagDriver for Hmc5883lDriver {
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError> {
+        // magnometer config
+        write_register(i2c, HMC5883L_ADDR, RegisterValue::new()
+            .with(HMC_AVERAGING, HmcAveraging::Samples8)
+            .with(HMC_RATE, rate)
+            .with(HMC_BIAS, HmcBias::Normal)).await?;
+
+        // set gain
+        write_register(i2c, HMC5883L_ADDR, RegisterValue::new().with(HMC_GAIN, HmcGain::Ga1_3)).await?;
+
+        // set mode
+        write_register(i2c, HMC5883L_ADDR, RegisterValue::new().with(HMC_MODE, HmcMode::Continuous)).await?;
+
+        Ok(Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Qmc5883lDriver;
+
+impl Qmc5883lDriver {
+    /// Closest rate at or above `rate`
+    fn rate(rate: MagRate) -> QmcRate {
+        match rate {
+            MagRate::Hz0_75 | MagRate::Hz1_5 | MagRate::Hz3 | MagRate::Hz7_5 => QmcRate::Hz10,
+            MagRate::Hz15 | MagRate::Hz30 => QmcRate::Hz50,
+            MagRate::Hz75 => QmcRate::Hz100,
+        }
+    }
+}
+
+impl MagDriver for Qmc5883lDriver {
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError> {
+        // soft reset
+        write_register(i2c, QMC5883L_ADDR, RegisterValue::new().with(QMC_SOFT_RESET, true)).await?;
+        Timer::after(QMC_RESET_TIME).await;
+
+        // recommended set/reset period
+        write_byte(i2c, QMC5883L_ADDR, QMC5883L_SET_RESET_PERIOD, QMC5883L_SET_RESET_PERIOD_DEFAULT).await?;
+
+        // magnometer config and mode
+        write_register(i2c, QMC5883L_ADDR, RegisterValue::new()
+            .with(QMC_OVERSAMPLING, QmcOversampling::Samples512)
+            .with(QMC_RANGE, QmcRange::Ga8)
+            .with(QMC_RATE, Self::rate(rate))
+            .with(QMC_MODE, QmcMode::Continuous)).await?;
+
+        Ok(Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Ak8963Driver;
+
+impl Ak8963Driver {
+    fn mode(rate: MagRate) -> Ak8963Mode {
+        match rate {
+            MagRate::Hz0_75 | MagRate::Hz1_5 | MagRate::Hz3 | MagRate::Hz7_5 => Ak8963Mode::Continuous8,
+            MagRate::Hz15 | MagRate::Hz30 | MagRate::Hz75 => Ak8963Mode::Continuous100,
+        }
+    }
+}
+
+impl MagDriver for Ak8963Driver {
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError> {
+        // soft reset
+        write_register(i2c, AK8963_ADDR, RegisterValue::new().with(AK8963_SOFT_RESET, true)).await?;
+        Timer::after(AK_MODE_SWITCH_TIME).await;
+
+        // read factory sensitivity adjustment
+        write_register(i2c, AK8963_ADDR, RegisterValue::new().with(AK8963_MODE, Ak8963Mode::FuseRom)).await?;
+        Timer::after(AK_MODE_SWITCH_TIME).await;
+        let mut asa = [0u8; 3];
+        read_bytes(i2c, AK8963_ADDR, AK8963_SENSITIVITY_ADJUST, &mut asa).await?;
+        write_register(i2c, AK8963_ADDR, RegisterValue::new().with(AK8963_MODE, Ak8963Mode::PowerDown)).await?;
+        Timer::after(AK_MODE_SWITCH_TIME).await;
+
+        // 16 bit output and continuous mode
+        write_register(i2c, AK8963_ADDR, RegisterValue::new().with(AK8963_OUTPUT_16BIT, true).with(AK8963_MODE, Self::mode(rate))).await?;
+
+        // same axis order as Magnetometer::decode
+        let adjust = |asa: u8| (asa as f32 - 128.0) / 256.0 + 1.0;
+        Ok(Vector3::new(adjust(asa[1]), adjust(asa[0]), adjust(asa[2])))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Ak09916Driver;
+
+impl Ak09916Driver {
+    fn mode(rate: MagRate) -> Ak09916Mode {
+        match rate {
+            MagRate::Hz0_75 | MagRate::Hz1_5 | MagRate::Hz3 | MagRate::Hz7_5 => Ak09916Mode::Continuous10,
+            MagRate::Hz15 => Ak09916Mode::Continuous20,
+            MagRate::Hz30 => Ak09916Mode::Continuous50,
+            MagRate::Hz75 => Ak09916Mode::Continuous100,
+        }
+    }
+}
+
+impl MagDriver for Ak09916Driver {
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError> {
+        // soft reset
+        write_register(i2c, AK09916_ADDR, RegisterValue::new().with(AK09916_SOFT_RESET, true)).await?;
+        Timer::after(AK_MODE_SWITCH_TIME).await;
+
+        // set mode
+        write_register(i2c, AK09916_ADDR, RegisterValue::new().with(AK09916_MODE, Self::mode(rate))).await?;
+
+        // the AK09916 has no sensitivity adjustment
+        Ok(Vector3::new(1.0, 1.0, 1.0))
+    }
+}
+
+// picks the driver of the detected chip
+impl MagDriver for Magnetometer {
+    async fn init<I: I2c>(&self, i2c: &mut I, rate: MagRate) -> Result<Vec3, BusError> {
+        match self {
+            Magnetometer::Hmc5883l => Hmc5883lDriver.init(i2c, rate).await,
+            Magnetometer::Qmc5883l => Qmc5883lDriver.init(i2c, rate).await,
+            Magnetometer::Ak8963 => Ak8963Driver.init(i2c, rate).await,
+            Magnetometer::Ak09916 => Ak09916Driver.init(i2c, rate).await,
+        }
+    }
+}
@@ -1,30 +1,97 @@
 #![no_std]
 #![no_main]
 #![feature(type_alias_impl_trait)]
+#![feature(async_fn_in_trait)]
 
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
 
 use cortex_m_rt::{exception, ExceptionFrame};
 use embassy_executor::Spawner;
-use embassy_stm32::gpio::{Level, Output, Speed};
+use embassy_stm32::exti::ExtiInput;
+use embassy_stm32::gpio::{Input, Level, Output, Pull, Speed};
 use embassy_stm32::interrupt;
-use embassy_stm32::time::Hertz;
 use embassy_stm32::usart::{Config, Uart};
-use embassy_stm32::i2c;
+use embassy_sync::blocking_mutex::raw::{NoopRawMutex, ThreadModeRawMutex};
+use embassy_sync::channel::Channel;
 use embassy_time::{Duration, Timer, Instant};
 use embedded_hal_async::i2c::I2c;
-use rtt_target::{rprintln, rtt_init_print};
+use rtt_target::{rprint, rprintln, rtt_init_print};
 
-use crate::gy87::Gy87;
+#[cfg(feature = "dmp")]
+use headtracker_rs::dmp::{DmpFirmware, DmpMode};
+use headtracker_rs::bmp180::{BaroOversampling, Bmp180};
+use headtracker_rs::bus::SharedBus;
+use headtracker_rs::calibration::{AccelCalibrator, Face};
+use headtracker_rs::gy87::{Gy87, Gy87Config};
+
+use crate::recoverable_i2c::RecoverableI2c;
 use crate::wifi::Wifi;
 
-mod constants;
-mod gy87;
-mod util;
+mod recoverable_i2c;
 mod wifi;
 
+/// InvenSense 6.12 MotionApps image, not part of the repository
+#[cfg(feature = "dmp")]
+static DMP_FIRMWARE: &[u8] = include_bytes!("../dmp/dmp612.bin");
+
+/// Drivers sharing I2C1, the GY-87 motion sensors and its BMP180
+const I2C_DEVICES: usize = 2;
+
+/// Time to turn the tracker onto the next face of the accel calibration
+const FACE_SETTLE: Duration = Duration::from_secs(5);
+/// Samples averaged per face of the accel calibration
+const FACE_SAMPLES: u16 = 200;
+
+/// Serialized poses on their way to the network task, new ones are dropped while it is full
+static POSITIONS: Channel<ThreadModeRawMutex, [u8; 48], 4> = Channel::new();
+
+/// Sends diagnostics to the RTT console
+struct RttSink;
+
+impl Write for RttSink {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        rprint!("{}", s);
+        Ok(())
+    }
+}
+
+/// Six position accel calibration, guided over RTT
+async fn calibrate_accel<I: I2c>(gy87: &mut Gy87<I>) {
+    let mut calibrator = AccelCalibrator::new();
+    for face in Face::ALL {
+        loop {
+            rprintln!("rest the tracker on {:?} and hold still", face);
+            Timer::after(FACE_SETTLE).await;
+            match gy87.collect_accel_face(&mut calibrator, face, FACE_SAMPLES).await {
+                Ok(()) => break,
+                Err(err) => rprintln!("{:?} failed, again: {:?}", face, err),
+            }
+        }
+    }
+
+    match calibrator.solve(true) {
+        Some(calibration) => {
+            rprintln!("accel calibration: {:?}", calibration);
+            gy87.set_accel_calibration(Some(calibration));
+        }
+        None => rprintln!("accel calibration failed"),
+    }
+}
+
+/// Sends poses over UDP so a slow UART write never holds up the next sensor read
+#[embassy_executor::task]
+async fn network(mut wifi: Wifi<'static>) {
+    loop {
+        let data = POSITIONS.recv().await;
+        if let Err(err) = wifi.send_pos_data(&data).await {
+            rprintln!("{:?}", err);
+        }
+    }
+}
+
 #[embassy_executor::main]
-async fn main(_spawner: Spawner) {
+async fn main(spawner: Spawner) {
     rtt_init_print!();
     let p = embassy_stm32::init(Default::default());
 
@@ -42,25 +109,44 @@ async fn main(_spawner: Spawner) {
     }
     Timer::after(Duration::from_millis(1000)).await;
     wifi.start_udp().await.unwrap();
+    spawner.spawn(network(wifi)).unwrap();
 
     rprintln!("wifi up!");
 
     let irq = interrupt::take!(I2C1_EV);
-    let i2c = i2c::I2c::new(
-        p.I2C1,
-        p.PB6,
-        p.PB7,
-        irq,
-        p.DMA1_CH0,
-        p.DMA1_CH6,
-        Hertz(400_000),
-        Default::default(),
-    );
-
-    let mut gy87 = Gy87::new(i2c);
-    gy87.start().unwrap();
+    let i2c = RecoverableI2c::new(p.I2C1, p.PB6, p.PB7, irq, p.DMA1_CH0, p.DMA1_CH6);
+    let bus = SharedBus::<NoopRawMutex, _, I2C_DEVICES>::new(i2c);
+
+    let mpu_int = Input::new(p.PB0, Pull::Down);
+    let mut mpu_int = ExtiInput::new(mpu_int, p.EXTI0);
+
+    let gy87_config = Gy87Config {
+        data_ready_interrupt: true,
+        altitude_as_z: true,
+        #[cfg(feature = "dmp")]
+        dmp: DmpMode::Primary,
+        #[cfg(feature = "dmp")]
+        dmp_firmware: Some(DmpFirmware(DMP_FIRMWARE)),
+        ..Default::default()
+    };
+    let bmp180 = Bmp180::new(bus.device().unwrap(), BaroOversampling::X8);
+    let mut gy87 = Gy87::new(bus.device().unwrap(), gy87_config).with_baro(bmp180);
+    if let Err(err) = gy87.start().await {
+        rprintln!("gy87 start failed: {:?}", err);
+        _ = gy87.diagnose(&mut RttSink).await;
+        panic!("gy87 start failed");
+    }
 
     rprintln!("gy87 up!");
+    if !gy87.gyro_calibrated() {
+        rprintln!("tracker moved during gyro calibration, using bias {:?}", gy87.gyro_bias());
+    }
+
+    // KEY held at boot runs the accel calibration
+    let key = Input::new(p.PA0, Pull::Up);
+    if key.is_low() {
+        calibrate_accel(&mut gy87).await;
+    }
 
     let mut led = Output::new(p.PC13, Level::Low, Speed::Low);
     led.set_low();
@@ -68,12 +154,24 @@ async fn main(_spawner: Spawner) {
     let mut prev = Instant::now();
     let mut i = 0;
     loop {
-        if let Ok(data) = gy87.update(&prev) {
-            prev = Instant::now();
-            let data = data.serialize();
-            match wifi.send_pos_data(&data).await {
-                Err(err) => rprintln!("{:?}", err),
-                _ => {}
+        if let Err(err) = gy87.wait_for_sample(&mut mpu_int).await {
+            rprintln!("{:?}", err);
+        }
+        match gy87.update(&prev).await {
+            Ok(data) => {
+                prev = Instant::now();
+                // a stale pose is worth less than the next one, don't wait for room
+                if POSITIONS.try_send(data.serialize()).is_err() {
+                    rprintln!("network busy, pose dropped");
+                }
+            }
+            Err(err) => {
+                rprintln!("{:?}", err);
+                match gy87.recover().await {
+                    Ok(Some(step)) => rprintln!("i2c recovery: {:?}", step),
+                    Ok(None) => {}
+                    Err(err) => rprintln!("i2c recovery failed: {:?}", err),
+                }
             }
         }
     }
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::vec::Vec;
+
+use embedded_hal_async::i2c::{ErrorKind, ErrorType, I2c, Operation};
+
+use crate::recovery::BusRecovery;
+
+/// Register file of one device, the address pointer auto increments like on the real chips
+struct Device {
+    registers: [u8; 256],
+    pointer: u8,
+}
+
+/// Bus with scripted devices for host tests. Reads are served from the register files and
+/// every write is logged as `(addr, bytes)` so tests can check exactly what a driver sent
+pub struct MockI2c {
+    devices: HashMap<u8, Device>,
+    writes: Vec<(u8, Vec<u8>)>,
+    /// Every transfer fails while set, like a bus that locked up
+    pub fail: bool,
+    /// Recovery is skipped while set, like a shared bus another driver is using
+    pub busy: bool,
+    pub unsticks: u16,
+    pub recreates: u16,
+}
+
+impl MockI2c {
+    pub fn new() -> Self {
+        Self {
+            devices: HashMap::new(),
+            writes: Vec::new(),
+            fail: false,
+            busy: false,
+            unsticks: 0,
+            recreates: 0,
+        }
+    }
+
+    /// Adds a device with all registers zero
+    pub fn device(&mut self, addr: u8) -> &mut Self {
+        self.devices.insert(addr, Device {
+            registers: [0; 256],
+            pointer: 0,
+        });
+        self
+    }
+
+    /// Presets registers from `reg` on, without logging a write
+    pub fn set(&mut self, addr: u8, reg: u8, values: &[u8]) -> &mut Self {
+        let device = self.devices.get_mut(&addr).expect("unknown device");
+        for (i, value) in values.iter().enumerate() {
+            device.registers[reg as usize + i] = *value;
+        }
+        self
+    }
+
+    pub fn writes(&self) -> &[(u8, Vec<u8>)] {
+        &self.writes
+    }
+
+    pub fn clear_writes(&mut self) {
+        self.writes.clear();
+    }
+
+    fn bus_read(&mut self, addr: u8, read: &mut [u8]) -> Result<(), ErrorKind> {
+        let device = self.present(addr)?;
+        for byte in read.iter_mut() {
+            *byte = device.registers[device.pointer as usize];
+            device.pointer = device.pointer.wrapping_add(1);
+        }
+        Ok(())
+    }
+
+    fn bus_write(&mut self, addr: u8, write: &[u8]) -> Result<(), ErrorKind> {
+        let device = self.present(addr)?;
+        if let Some((reg, values)) = write.split_first() {
+            device.pointer = *reg;
+            for value in values {
+                device.registers[device.pointer as usize] = *value;
+                device.pointer = device.pointer.wrapping_add(1);
+            }
+        }
+        self.writes.push((addr, write.to_vec()));
+        Ok(())
+    }
+
+    /// Only a register pointer write, not logged
+    fn bus_write_read(&mut self, addr: u8, write: &[u8], read: &mut [u8]) -> Result<(), ErrorKind> {
+        let device = self.present(addr)?;
+        if let Some(reg) = write.first() {
+            device.pointer = *reg;
+        }
+        self.bus_read(addr, read)
+    }
+
+    fn present(&mut self, addr: u8) -> Result<&mut Device, ErrorKind> {
+        if self.fail {
+            return Err(ErrorKind::Other);
+        }
+        self.devices.get_mut(&addr).ok_or(ErrorKind::Other)
+    }
+}
+
+impl ErrorType for MockI2c {
+    type Error = ErrorKind;
+}
+
+impl I2c for MockI2c {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus_read(address, read)
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.bus_write(address, write)
+    }
+
+    async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus_write_read(address, write, read)
+    }
+
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        embedded_hal_1::i2c::I2c::transaction(self, address, operations)
+    }
+}
+
+impl embedded_hal_1::i2c::I2c for MockI2c {
+    fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus_read(address, read)
+    }
+
+    fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        self.bus_write(address, write)
+    }
+
+    fn write_iter<B: IntoIterator<Item = u8>>(&mut self, address: u8, bytes: B) -> Result<(), Self::Error> {
+        self.bus_write(address, &bytes.into_iter().collect::<Vec<_>>())
+    }
+
+    fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus_write_read(address, write, read)
+    }
+
+    fn write_iter_read<B: IntoIterator<Item = u8>>(&mut self, address: u8, bytes: B, read: &mut [u8]) -> Result<(), Self::Error> {
+        self.bus_write_read(address, &bytes.into_iter().collect::<Vec<_>>(), read)
+    }
+
+    fn transaction<'a>(&mut self, address: u8, operations: &mut [Operation<'a>]) -> Result<(), Self::Error> {
+        for operation in operations {
+            match operation {
+                Operation::Read(read) => self.bus_read(address, read)?,
+                Operation::Write(write) => self.bus_write(address, write)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn transaction_iter<'a, O: IntoIterator<Item = Operation<'a>>>(&mut self, address: u8, operations: O) -> Result<(), Self::Error> {
+        for mut operation in operations {
+            embedded_hal_1::i2c::I2c::transaction(self, address, core::slice::from_mut(&mut operation))?;
+        }
+        Ok(())
+    }
+}
+
+impl BusRecovery for MockI2c {
+    fn unstick(&mut self) -> bool {
+        if !self.busy {
+            self.unsticks += 1;
+        }
+        !self.busy
+    }
+
+    fn recreate(&mut self) -> bool {
+        if !self.busy {
+            self.recreates += 1;
+        }
+        !self.busy
+    }
+}
@@ -0,0 +1,179 @@
+use fusion_rs::nalgebra::{Matrix3, Rotation3, UnitQuaternion};
+use fusion_rs::Vec3;
+use libm::fabsf;
+
+/// Largest error of a rotation matrix entry and its determinant `Mounting::from_matrix` accepts
+const ROTATION_TOLERANCE: f32 = 1e-3;
+
+/// Sensor axis, accel, gyro and mag share the same sensor frame
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Axis {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl Axis {
+    pub const ALL: [Axis; 6] = [Axis::PosX, Axis::NegX, Axis::PosY, Axis::NegY, Axis::PosZ, Axis::NegZ];
+
+    fn vector(&self) -> Vec3 {
+        match self {
+            Axis::PosX => Vec3::new(1.0, 0.0, 0.0),
+            Axis::NegX => Vec3::new(-1.0, 0.0, 0.0),
+            Axis::PosY => Vec3::new(0.0, 1.0, 0.0),
+            Axis::NegY => Vec3::new(0.0, -1.0, 0.0),
+            Axis::PosZ => Vec3::new(0.0, 0.0, 1.0),
+            Axis::NegZ => Vec3::new(0.0, 0.0, -1.0),
+        }
+    }
+}
+
+/// Rotation from the sensor frame into the tracker frame, applied to accel, gyro and mag
+/// before fusion so all three stay aligned
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Mounting {
+    rotation: Matrix3<f32>,
+}
+
+impl Mounting {
+    pub fn identity() -> Self {
+        Self {
+            rotation: Matrix3::identity(),
+        }
+    }
+
+    /// One of the 24 axis aligned mountings, `x` and `y` are the sensor axes pointing along
+    /// the tracker x and y axes. Returns `None` unless they are perpendicular
+    pub fn axis_aligned(x: Axis, y: Axis) -> Option<Self> {
+        let x = x.vector();
+        let y = y.vector();
+        if x.dot(&y) != 0.0 {
+            return None;
+        }
+
+        // rows are the tracker axes in sensor coordinates, z keeps the frame right handed
+        Some(Self {
+            rotation: Matrix3::from_rows(&[x.transpose(), y.transpose(), x.cross(&y).transpose()]),
+        })
+    }
+
+    /// Arbitrary mounting, `rotation` takes a sensor frame vector into the tracker frame.
+    /// Returns `None` unless it is orthonormal with a determinant of 1
+    pub fn from_matrix(rotation: Matrix3<f32>) -> Option<Self> {
+        let error = rotation * rotation.transpose() - Matrix3::identity();
+        if error.iter().any(|e| fabsf(*e) > ROTATION_TOLERANCE) || fabsf(rotation.determinant() - 1.0) > ROTATION_TOLERANCE {
+            return None;
+        }
+
+        Some(Self {
+            rotation,
+        })
+    }
+
+    /// Sensor frame rotated by roll, pitch and yaw in degrees relative to the tracker,
+    /// applied in that order
+    pub fn from_euler(roll: f32, pitch: f32, yaw: f32) -> Self {
+        let rotation = Rotation3::from_euler_angles(roll.to_radians(), pitch.to_radians(), yaw.to_radians());
+        Self {
+            rotation: rotation.into_inner(),
+        }
+    }
+
+    pub fn apply(&self, v: &Vec3) -> Vec3 {
+        self.rotation * v
+    }
+
+    /// Orientation of the tracker from the orientation of the sensor
+    pub fn apply_orientation(&self, orientation: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+        let mounting = UnitQuaternion::from_rotation_matrix(&Rotation3::from_matrix_unchecked(self.rotation));
+        orientation * mounting.inverse()
+    }
+}
+
+impl Default for Mounting {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use fusion_rs::nalgebra::Vector3;
+
+    use super::*;
+
+    /// Every one of the 24 axis aligned mountings
+    pub(crate) fn all_axis_aligned() -> Vec<Mounting> {
+        let mut mountings = Vec::new();
+        for x in Axis::ALL {
+            for y in Axis::ALL {
+                if let Some(mounting) = Mounting::axis_aligned(x, y) {
+                    mountings.push(mounting);
+                }
+            }
+        }
+        mountings
+    }
+
+    #[test]
+    fn axis_aligned_mountings_are_24_distinct_rotations() {
+        let mountings = all_axis_aligned();
+        assert_eq!(mountings.len(), 24);
+
+        let probe = Vector3::new(1.0, 2.0, 3.0);
+        for (i, mounting) in mountings.iter().enumerate() {
+            assert_eq!(Mounting::from_matrix(mounting.rotation), Some(*mounting));
+            for other in &mountings[i + 1..] {
+                assert_ne!(mounting.apply(&probe), other.apply(&probe));
+            }
+        }
+    }
+
+    #[test]
+    fn axis_aligned_maps_the_named_axes() {
+        // sensor y along the tracker x, sensor -x along the tracker y
+        let mounting = Mounting::axis_aligned(Axis::PosY, Axis::NegX).unwrap();
+        assert_eq!(mounting.apply(&Vector3::new(0.0, 1.0, 0.0)), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(mounting.apply(&Vector3::new(-1.0, 0.0, 0.0)), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(mounting.apply(&Vector3::new(0.0, 0.0, 1.0)), Vector3::new(0.0, 0.0, 1.0));
+
+        assert_eq!(Mounting::axis_aligned(Axis::PosX, Axis::NegX), None);
+        assert_eq!(Mounting::axis_aligned(Axis::PosZ, Axis::PosZ), None);
+    }
+
+    #[test]
+    fn from_matrix_only_accepts_rotations() {
+        assert_eq!(Mounting::from_matrix(Matrix3::identity()), Some(Mounting::identity()));
+        let euler = Mounting::from_euler(30.0, -45.0, 120.0);
+        assert_eq!(Mounting::from_matrix(euler.rotation), Some(euler));
+
+        // scaled, mirrored and sheared
+        assert_eq!(Mounting::from_matrix(Matrix3::identity() * 2.0), None);
+        assert_eq!(Mounting::from_matrix(Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, -1.0))), None);
+        assert_eq!(Mounting::from_matrix(Matrix3::new(1.0, 0.1, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0)), None);
+    }
+
+    #[test]
+    fn orientation_agrees_with_rotated_vectors() {
+        let mut mountings = all_axis_aligned();
+        mountings.push(Mounting::from_euler(30.0, -45.0, 120.0));
+        let orientations = [
+            UnitQuaternion::identity(),
+            UnitQuaternion::from_euler_angles(0.4, -1.1, 2.5),
+            UnitQuaternion::from_euler_angles(-2.0, 0.3, -0.7),
+        ];
+        let sensor = Vector3::new(0.3, -0.5, 0.8);
+
+        // the same vector seen from the sensor and from the tracker points the same way in the world
+        for mounting in &mountings {
+            for orientation in &orientations {
+                let tracker = mounting.apply_orientation(orientation);
+                let error = tracker * mounting.apply(&sensor) - orientation * sensor;
+                assert!(error.norm() < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use embassy_stm32::gpio::{Input, Level, OutputOpenDrain, Pull, Speed};
+use embassy_stm32::i2c::{self, I2c as Stm32I2c};
+use embassy_stm32::interrupt::{self, Interrupt};
+use embassy_stm32::peripherals::{DMA1_CH0, DMA1_CH6, I2C1, PB6, PB7};
+use embassy_stm32::time::Hertz;
+use embassy_time::{block_for, Duration};
+use embedded_hal_async::i2c::{ErrorType, I2c, Operation};
+use headtracker_rs::recovery::BusRecovery;
+
+type I2cType = Stm32I2c<'static, I2C1, DMA1_CH0, DMA1_CH6>;
+
+const I2C_FREQUENCY: u32 = 400_000;
+/// A device stuck in the middle of a byte lets go of SDA after at most 9 clocks
+const UNSTICK_CLOCKS: u8 = 9;
+/// Half period of the bit banged clock, 100kHz
+const UNSTICK_HALF_PERIOD: Duration = Duration::from_micros(5);
+
+/// I2C1 on PB6/PB7 that can unstick and re-create itself
+pub struct RecoverableI2c {
+    i2c: Option<I2cType>,
+}
+
+impl RecoverableI2c {
+    /// Consumes the peripherals so nothing else can use them, the driver is re-created from them on recovery
+    pub fn new(_peri: I2C1, _scl: PB6, _sda: PB7, _irq: interrupt::I2C1_EV, _tx_dma: DMA1_CH0, _rx_dma: DMA1_CH6) -> Self {
+        Self {
+            i2c: Some(Self::create()),
+        }
+    }
+
+    fn create() -> I2cType {
+        // `new` took ownership of all of them and the previous driver is dropped before this runs
+        let (peri, scl, sda, irq, tx_dma, rx_dma) = unsafe {
+            (I2C1::steal(), PB6::steal(), PB7::steal(), interrupt::I2C1_EV::steal(), DMA1_CH0::steal(), DMA1_CH6::steal())
+        };
+        Stm32I2c::new(peri, scl, sda, irq, tx_dma, rx_dma, Hertz(I2C_FREQUENCY), Default::default())
+    }
+
+    fn bus(&mut self) -> Result<&mut I2cType, i2c::Error> {
+        self.i2c.as_mut().ok_or(i2c::Error::Bus)
+    }
+}
+
+impl BusRecovery for RecoverableI2c {
+    fn unstick(&mut self) -> bool {
+        // the driver has to let go of the pins first
+        self.i2c = None;
+
+        let mut scl = OutputOpenDrain::new(unsafe { PB6::steal() }, Level::High, Speed::Low, Pull::None);
+        let sda = Input::new(unsafe { PB7::steal() }, Pull::Up);
+        for _ in 0..UNSTICK_CLOCKS {
+            if sda.is_high() {
+                break;
+            }
+            scl.set_low();
+            block_for(UNSTICK_HALF_PERIOD);
+            scl.set_high();
+            block_for(UNSTICK_HALF_PERIOD);
+        }
+        drop(sda);
+
+        // STOP, SDA rises while SCL is high
+        scl.set_low();
+        let mut sda = OutputOpenDrain::new(unsafe { PB7::steal() }, Level::Low, Speed::Low, Pull::None);
+        block_for(UNSTICK_HALF_PERIOD);
+        scl.set_high();
+        block_for(UNSTICK_HALF_PERIOD);
+        sda.set_high();
+        block_for(UNSTICK_HALF_PERIOD);
+        drop(sda);
+        drop(scl);
+
+        self.i2c = Some(Self::create());
+        true
+    }
+
+    fn recreate(&mut self) -> bool {
+        self.i2c = None;
+        self.i2c = Some(Self::create());
+        true
+    }
+}
+
+impl ErrorType for RecoverableI2c {
+    type Error = i2c::Error;
+}
+
+impl I2c for RecoverableI2c {
+    async fn read(&mut self, address: u8, read: &mut [u8]) -> Result<(), Self::Error> {
+        I2c::read(self.bus()?, address, read).await
+    }
+
+    async fn write(&mut self, address: u8, write: &[u8]) -> Result<(), Self::Error> {
+        I2c::write(self.bus()?, address, write).await
+    }
+
+    async fn write_read(&mut self, address: u8, write: &[u8], read: &mut [u8]) -> Result<(), Self::Error> {
+        I2c::write_read(self.bus()?, address, write, read).await
+    }
+
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        I2c::transaction(self.bus()?, address, operations).await
+    }
+}
@@ -0,0 +1,51 @@
+/// Bus that can be brought back after it locked up. Both actions return whether they ran,
+/// a bus shared with other drivers can't be touched while one of them uses it
+pub trait BusRecovery {
+    /// Clocks SCL by hand until the device holding SDA low releases it and sends a STOP
+    fn unstick(&mut self) -> bool;
+    /// Drops the I2C peripheral, which resets it, and sets it up again
+    fn recreate(&mut self) -> bool;
+}
+
+/// Escalation ladder, every step is taken once its consecutive error count is reached and
+/// the ladder starts over after a re-init
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct RecoveryPolicy {
+    pub unstick_after: u16,
+    pub recreate_after: u16,
+    /// Full sensor re-init, also the length of the ladder
+    pub reinit_after: u16,
+}
+
+impl RecoveryPolicy {
+    pub fn step(&self, errors: u16) -> Option<RecoveryStep> {
+        if errors == 0 {
+            return None;
+        }
+
+        let reinit_after = self.reinit_after.max(1);
+        match (errors - 1) % reinit_after + 1 {
+            e if e == reinit_after => Some(RecoveryStep::Reinit),
+            e if e == self.recreate_after => Some(RecoveryStep::Recreate),
+            e if e == self.unstick_after => Some(RecoveryStep::Unstick),
+            _ => None,
+        }
+    }
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self {
+            unstick_after: 3,
+            recreate_after: 6,
+            reinit_after: 10,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum RecoveryStep {
+    Unstick,
+    Recreate,
+    Reinit,
+}
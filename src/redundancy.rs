@@ -0,0 +1,327 @@
+use embassy_time::{Duration, Instant};
+use libm::{fabsf, sqrtf};
+
+use crate::constants::ACCEL_GYRO_PACKET;
+
+/// Gyro difference in °/s above which the two IMUs disagree
+const GYRO_DISAGREEMENT: f32 = 10.0;
+/// Accel difference in g above which the two IMUs disagree
+const ACCEL_DISAGREEMENT: f32 = 0.2;
+/// Samples in a row the IMUs have to disagree before the worse one is dropped
+const DISAGREEMENT_SAMPLES: u16 = 50;
+/// Time the exact same sample may come back before the IMU counts as frozen, long enough
+/// that polling faster than the output rate doesn't trigger it
+const FROZEN_TIME: Duration = Duration::from_millis(500);
+/// Samples in a row a dropped IMU has to agree with the other one before it is trusted again
+const RECOVERY_SAMPLES: u16 = 200;
+/// Smoothing of the gravity error used to pick the worse IMU
+const GRAVITY_ERROR_ALPHA: f32 = 0.01;
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ImuHealth {
+    Healthy,
+    Failed,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Channel {
+    health: ImuHealth,
+    last: Option<[i16; 7]>,
+    unchanged_since: Option<Instant>,
+    good: u16,
+    /// Smoothed deviation of the accel magnitude from 1g while in use
+    gravity_error: f32,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            health: ImuHealth::Healthy,
+            last: None,
+            unchanged_since: None,
+            good: 0,
+            gravity_error: 0.0,
+        }
+    }
+
+    fn fail(&mut self) {
+        self.health = ImuHealth::Failed;
+        self.good = 0;
+    }
+
+    fn observe(&mut self, sample: Option<[i16; 7]>, accel_scale: f32, now: Instant) {
+        let sample = match sample {
+            Some(sample) => sample,
+            None => return self.fail(),
+        };
+
+        // a sensor that stopped converting keeps returning the exact same registers
+        if self.last == Some(sample) {
+            let since = *self.unchanged_since.get_or_insert(now);
+            if now - since >= FROZEN_TIME {
+                return self.fail();
+            }
+        } else {
+            self.unchanged_since = None;
+        }
+        self.last = Some(sample);
+
+        let accel = accel(&sample, accel_scale);
+        let magnitude = sqrtf(accel.iter().map(|a| a * a).sum());
+        self.gravity_error += (fabsf(magnitude - 1.0) - self.gravity_error) * GRAVITY_ERROR_ALPHA;
+    }
+
+    /// Counts towards trusting a failed IMU again, `agrees` is whether its sample matched
+    /// the other IMU's. The gravity error is kept so a disagreement right after still
+    /// blames the IMU with the worse record
+    fn recover(&mut self, agrees: bool) {
+        if self.health == ImuHealth::Healthy {
+            return;
+        }
+        if !agrees {
+            self.good = 0;
+            return;
+        }
+        self.good += 1;
+        if self.good >= RECOVERY_SAMPLES {
+            self.health = ImuHealth::Healthy;
+        }
+    }
+}
+
+/// Health tracking and combining of two MPU6050s sampling the same motion
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Redundancy {
+    channels: [Channel; 2],
+    disagreement: u16,
+}
+
+impl Redundancy {
+    pub fn new() -> Self {
+        Self {
+            channels: [Channel::new(); 2],
+            disagreement: 0,
+        }
+    }
+
+    /// Averages both samples while the IMUs agree and falls back to the healthy one otherwise,
+    /// a `None` sample is a failed read. Returns `None` if neither delivered a sample
+    pub fn combine(
+        &mut self,
+        samples: [Option<&[u8; ACCEL_GYRO_PACKET]>; 2],
+        gyro_scale: f32,
+        accel_scale: f32,
+        now: Instant,
+    ) -> Option<[u8; ACCEL_GYRO_PACKET]> {
+        let words = samples.map(|sample| sample.map(words));
+        for (channel, sample) in self.channels.iter_mut().zip(words) {
+            channel.observe(sample, accel_scale, now);
+        }
+
+        // a failed IMU only earns trust back by matching the other one, which has to be
+        // healthy unless both failed and only have each other to compare against
+        let agrees = match words {
+            [Some(a), Some(b)] => agree(&a, &b, gyro_scale, accel_scale),
+            _ => false,
+        };
+        let health = self.health();
+        for (i, channel) in self.channels.iter_mut().enumerate() {
+            let reference_ok = health[1 - i] == ImuHealth::Healthy || health == [ImuHealth::Failed; 2];
+            channel.recover(agrees && reference_ok);
+        }
+
+        let healthy = self.channels.map(|channel| channel.health == ImuHealth::Healthy);
+        let chosen = match (words, healthy) {
+            ([Some(a), Some(b)], [true, true]) => {
+                if agree(&a, &b, gyro_scale, accel_scale) {
+                    self.disagreement = 0;
+                    return Some(bytes(&average(&a, &b)));
+                }
+
+                // two sensors can't tell which one is right, trust the one that
+                // reported gravity more accurately so far
+                let worse = if self.channels[0].gravity_error > self.channels[1].gravity_error { 0 } else { 1 };
+                self.disagreement += 1;
+                if self.disagreement >= DISAGREEMENT_SAMPLES {
+                    self.channels[worse].fail();
+                    self.disagreement = 0;
+                }
+                1 - worse
+            }
+            ([Some(_), _], [true, _]) => 0,
+            ([_, Some(_)], [_, true]) => 1,
+            // nothing healthy, a suspicious reading still beats none
+            ([Some(_), _], _) => 0,
+            ([_, Some(_)], _) => 1,
+            ([None, None], _) => return None,
+        };
+
+        words[chosen].map(|sample| bytes(&sample))
+    }
+
+    /// Health of the IMU at 0x68 and the one at 0x69
+    pub fn health(&self) -> [ImuHealth; 2] {
+        self.channels.map(|channel| channel.health)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Accel x/y/z, temperature, gyro x/y/z
+fn words(sample: &[u8; ACCEL_GYRO_PACKET]) -> [i16; 7] {
+    let mut words = [0i16; 7];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = i16::from_be_bytes([sample[2 * i], sample[2 * i + 1]]);
+    }
+    words
+}
+
+fn bytes(words: &[i16; 7]) -> [u8; ACCEL_GYRO_PACKET] {
+    let mut sample = [0u8; ACCEL_GYRO_PACKET];
+    for (i, word) in words.iter().enumerate() {
+        sample[2 * i..2 * i + 2].copy_from_slice(&word.to_be_bytes());
+    }
+    sample
+}
+
+fn average(a: &[i16; 7], b: &[i16; 7]) -> [i16; 7] {
+    let mut average = [0i16; 7];
+    for i in 0..7 {
+        average[i] = ((a[i] as i32 + b[i] as i32) / 2) as i16;
+    }
+    average
+}
+
+fn accel(words: &[i16; 7], accel_scale: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| words[i] as f32 * accel_scale / 32768.0)
+}
+
+fn agree(a: &[i16; 7], b: &[i16; 7], gyro_scale: f32, accel_scale: f32) -> bool {
+    let accel_ok = (0..3).all(|i| fabsf((a[i] as f32 - b[i] as f32) * accel_scale / 32768.0) <= ACCEL_DISAGREEMENT);
+    let gyro_ok = (4..7).all(|i| fabsf((a[i] as f32 - b[i] as f32) * gyro_scale / 32768.0) <= GYRO_DISAGREEMENT);
+    accel_ok && gyro_ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Full scales of the 250 dps and 2g ranges
+    const GYRO_SCALE: f32 = 250.0;
+    const ACCEL_SCALE: f32 = 2.0;
+    /// 1g and 1.1g at 2g
+    const ONE_G: i16 = 16384;
+    const OFF_G: i16 = 18022;
+    /// About 15 °/s at 250 dps, more than the IMUs may differ by
+    const GYRO_APART: i16 = 2000;
+
+    /// Level sample, `noise` keeps consecutive samples from being identical
+    fn sample(accel_z: i16, gyro_x: i16, noise: u64) -> [u8; ACCEL_GYRO_PACKET] {
+        bytes(&[(noise % 2) as i16, 0, accel_z, 0, gyro_x, 0, 0])
+    }
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn combine(redundancy: &mut Redundancy, a: Option<[u8; ACCEL_GYRO_PACKET]>, b: Option<[u8; ACCEL_GYRO_PACKET]>, now: Instant) -> Option<[u8; ACCEL_GYRO_PACKET]> {
+        redundancy.combine([a.as_ref(), b.as_ref()], GYRO_SCALE, ACCEL_SCALE, now)
+    }
+
+    /// IMU 0 reads 1g and IMU 1 reads 1.1g, close enough to agree but IMU 1 ends up with
+    /// the worse gravity record, then IMU 1's gyro drifts off until it is dropped
+    fn drop_second(redundancy: &mut Redundancy, t: &mut u64) {
+        for _ in 0..100 {
+            *t += 1;
+            assert!(combine(redundancy, Some(sample(ONE_G, 0, *t)), Some(sample(OFF_G, 0, *t)), at(*t * 10)).is_some());
+        }
+        for _ in 0..DISAGREEMENT_SAMPLES {
+            *t += 1;
+            let a = sample(ONE_G, 0, *t);
+            assert_eq!(combine(redundancy, Some(a), Some(sample(OFF_G, GYRO_APART, *t)), at(*t * 10)), Some(a));
+        }
+        assert_eq!(redundancy.health(), [ImuHealth::Healthy, ImuHealth::Failed]);
+    }
+
+    #[test]
+    fn averages_while_the_imus_agree() {
+        let mut redundancy = Redundancy::new();
+        let combined = combine(&mut redundancy, Some(bytes(&[0, 0, 16384, 100, 100, 0, -10])), Some(bytes(&[2, 0, 16400, 300, 110, 0, -20])), at(0));
+        assert_eq!(combined, Some(bytes(&[1, 0, 16392, 200, 105, 0, -15])));
+        assert_eq!(redundancy.health(), [ImuHealth::Healthy; 2]);
+    }
+
+    #[test]
+    fn falls_back_when_a_read_fails() {
+        let mut redundancy = Redundancy::new();
+        let b = sample(ONE_G, 0, 0);
+        assert_eq!(combine(&mut redundancy, None, Some(b), at(0)), Some(b));
+        assert_eq!(redundancy.health(), [ImuHealth::Failed, ImuHealth::Healthy]);
+        assert_eq!(combine(&mut redundancy, None, None, at(10)), None);
+    }
+
+    #[test]
+    fn drops_the_imu_with_the_worse_gravity_record() {
+        let mut redundancy = Redundancy::new();
+        drop_second(&mut redundancy, &mut 0);
+    }
+
+    #[test]
+    fn recovers_only_after_agreeing_again() {
+        let mut redundancy = Redundancy::new();
+        let mut t = 0;
+        drop_second(&mut redundancy, &mut t);
+
+        let agreeing = |redundancy: &mut Redundancy, samples: u16, t: &mut u64| {
+            for _ in 0..samples {
+                *t += 1;
+                let a = sample(ONE_G, 0, *t);
+                assert_eq!(combine(redundancy, Some(a), Some(sample(OFF_G, 0, *t)), at(*t * 10)), Some(a));
+            }
+        };
+
+        // a disagreement on the way resets the count
+        agreeing(&mut redundancy, RECOVERY_SAMPLES - 1, &mut t);
+        t += 1;
+        combine(&mut redundancy, Some(sample(ONE_G, 0, t)), Some(sample(OFF_G, GYRO_APART, t)), at(t * 10));
+        agreeing(&mut redundancy, RECOVERY_SAMPLES - 1, &mut t);
+        assert_eq!(redundancy.health(), [ImuHealth::Healthy, ImuHealth::Failed]);
+
+        agreeing(&mut redundancy, 1, &mut t);
+        assert_eq!(redundancy.health(), [ImuHealth::Healthy; 2]);
+
+        // the recovered IMU still has the worse gravity record and loses the next disagreement
+        t += 1;
+        let a = sample(ONE_G, 0, t);
+        assert_eq!(combine(&mut redundancy, Some(a), Some(sample(OFF_G, GYRO_APART, t)), at(t * 10)), Some(a));
+    }
+
+    #[test]
+    fn a_missing_reference_does_not_count_towards_recovery() {
+        let mut redundancy = Redundancy::new();
+        let b = sample(ONE_G, 0, 0);
+        combine(&mut redundancy, None, Some(b), at(0));
+
+        for t in 1..=RECOVERY_SAMPLES as u64 {
+            combine(&mut redundancy, Some(sample(ONE_G, 0, t)), None, at(t * 10));
+        }
+        assert_eq!(redundancy.health(), [ImuHealth::Failed; 2]);
+    }
+
+    #[test]
+    fn frozen_needs_time_not_just_repeats() {
+        let mut redundancy = Redundancy::new();
+        let frozen = sample(ONE_G, 0, 0);
+
+        // polled faster than the IMU converts, the same registers come back many times
+        for t in 0..400 {
+            combine(&mut redundancy, Some(frozen), Some(sample(ONE_G, 0, t)), at(t));
+        }
+        assert_eq!(redundancy.health(), [ImuHealth::Healthy; 2]);
+
+        combine(&mut redundancy, Some(frozen), Some(sample(ONE_G, 0, 1)), at(600));
+        assert_eq!(redundancy.health(), [ImuHealth::Failed, ImuHealth::Healthy]);
+    }
+}
@@ -0,0 +1,662 @@
+use core::marker::PhantomData;
+
+use embedded_hal_async::i2c::I2c;
+
+use crate::bmp180::{BaroOversampling, Conversion};
+use crate::constants::*;
+use crate::gy87::{AccelRange, BusError, DlpfBandwidth, GyroRange, MagRate};
+
+/// A register of a chip, the bus address is passed along with it since some chips can sit
+/// at more than one
+pub trait Register {
+    const ADDR: u8;
+}
+
+/// Value stored in a bit field
+pub trait FieldValue: Copy {
+    /// Largest raw value, checked against the width of every field that holds this type
+    const MAX: u8;
+
+    fn bits(self) -> u8;
+    fn from_bits(bits: u8) -> Option<Self>;
+}
+
+/// Bits `HIGH` down to `LOW` of register `R`, numbered like the datasheets do. Fields are
+/// declared as consts so a field that doesn't fit its register, or a value type that
+/// doesn't fit the field, fails to compile
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Field<R, V> {
+    high: u8,
+    low: u8,
+    _marker: PhantomData<(R, V)>,
+}
+
+impl<R: Register, V: FieldValue> Field<R, V> {
+    /// ```
+    /// use headtracker_rs::gy87::GyroRange;
+    /// use headtracker_rs::registers::{Field, GyroConfig};
+    ///
+    /// const FULL_SCALE: Field<GyroConfig, GyroRange> = Field::new(4, 3);
+    /// assert_eq!(FULL_SCALE.mask(), 0x18);
+    /// ```
+    ///
+    /// A field past bit 7 doesn't compile
+    ///
+    /// ```compile_fail
+    /// use headtracker_rs::registers::{Field, GyroConfig};
+    ///
+    /// const TOO_HIGH: Field<GyroConfig, bool> = Field::new(8, 8);
+    /// assert_eq!(TOO_HIGH.mask(), 0);
+    /// ```
+    ///
+    /// Neither does a value type wider than the field
+    ///
+    /// ```compile_fail
+    /// use headtracker_rs::gy87::GyroRange;
+    /// use headtracker_rs::registers::{Field, GyroConfig};
+    ///
+    /// const TOO_NARROW: Field<GyroConfig, GyroRange> = Field::new(3, 3);
+    /// assert_eq!(TOO_NARROW.mask(), 0);
+    /// ```
+    pub const fn new(high: u8, low: u8) -> Self {
+        assert!(high < 8 && low <= high, "field does not fit in its register");
+        assert!((V::MAX as u16) < 1 << (high - low + 1), "value type does not fit in the field");
+        Self {
+            high,
+            low,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn mask(&self) -> u8 {
+        (((1u16 << (self.high - self.low + 1)) - 1) as u8) << self.low
+    }
+
+    /// `reg` with this field replaced by `value`
+    pub fn apply(&self, reg: u8, value: V) -> u8 {
+        (reg & !self.mask()) | ((value.bits() << self.low) & self.mask())
+    }
+
+    pub fn extract(&self, reg: u8) -> Option<V> {
+        V::from_bits((reg & self.mask()) >> self.low)
+    }
+}
+
+/// Whole register content built from its fields, for registers written without reading them first
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct RegisterValue<R> {
+    bits: u8,
+    _marker: PhantomData<R>,
+}
+
+impl<R: Register> RegisterValue<R> {
+    pub fn new() -> Self {
+        Self {
+            bits: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Only accepts fields of this register
+    pub fn with<V: FieldValue>(mut self, field: Field<R, V>, value: V) -> Self {
+        self.bits = field.apply(self.bits, value);
+        self
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.bits
+    }
+}
+
+/// Raw number that fits in `WIDTH` bits
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub struct Bits<const WIDTH: u8>(u8);
+
+impl<const WIDTH: u8> Bits<WIDTH> {
+    pub fn new(value: u8) -> Option<Self> {
+        if (value as u16) < 1 << WIDTH {
+            Some(Self(value))
+        } else {
+            None
+        }
+    }
+}
+
+impl<const WIDTH: u8> FieldValue for Bits<WIDTH> {
+    const MAX: u8 = ((1u16 << WIDTH) - 1) as u8;
+
+    fn bits(self) -> u8 {
+        self.0
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        Self::new(bits)
+    }
+}
+
+impl FieldValue for bool {
+    const MAX: u8 = 1;
+
+    fn bits(self) -> u8 {
+        self as u8
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        Some(bits != 0)
+    }
+}
+
+/// Implements `FieldValue` for an enum from its variant to bits mapping
+macro_rules! field_value {
+    ($name:ty { $($variant:path => $bits:expr),+ $(,)? }) => {
+        impl FieldValue for $name {
+            const MAX: u8 = {
+                let mut max = 0;
+                $(if $bits > max { max = $bits; })+
+                max
+            };
+
+            fn bits(self) -> u8 {
+                match self {
+                    $($variant => $bits,)+
+                }
+            }
+
+            fn from_bits(bits: u8) -> Option<Self> {
+                match bits {
+                    $(b if b == $bits => Some($variant),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+macro_rules! register {
+    ($name:ident, $addr:expr) => {
+        #[derive(Debug, Eq, PartialEq, Copy, Clone)]
+        pub struct $name;
+
+        impl Register for $name {
+            const ADDR: u8 = $addr;
+        }
+    };
+}
+
+pub async fn read_byte<I: I2c>(i2c: &mut I, device: u8, addr: u8) -> Result<u8, BusError> {
+    let mut rx_buffer: [u8; 1] = [0; 1];
+    read_bytes(i2c, device, addr, &mut rx_buffer).await?;
+    Ok(rx_buffer[0])
+}
+
+pub async fn read_bytes<I: I2c>(i2c: &mut I, device: u8, addr: u8, buffer: &mut [u8]) -> Result<(), BusError> {
+    i2c.write_read(device, &[addr], buffer).await.map_err(|_| BusError::BusReadWrite)?;
+    Ok(())
+}
+
+pub async fn write_byte<I: I2c>(i2c: &mut I, device: u8, addr: u8, value: u8) -> Result<(), BusError> {
+    i2c.write(device, &[addr, value]).await.map_err(|_| BusError::BusWrite)?;
+    Ok(())
+}
+
+/// Read-modify-write of a single field
+pub async fn modify<I: I2c, R: Register, V: FieldValue>(i2c: &mut I, device: u8, field: Field<R, V>, value: V) -> Result<(), BusError> {
+    let reg = read_byte(i2c, device, R::ADDR).await?;
+    write_byte(i2c, device, R::ADDR, field.apply(reg, value)).await
+}
+
+pub async fn write_register<I: I2c, R: Register>(i2c: &mut I, device: u8, value: RegisterValue<R>) -> Result<(), BusError> {
+    write_byte(i2c, device, R::ADDR, value.bits()).await
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ClockSource {
+    Internal,
+    /// PLL with the gyro X reference on the MPU6050, best available clock on the newer chips
+    Pll,
+}
+
+field_value!(ClockSource {
+    ClockSource::Internal => 0,
+    ClockSource::Pll => 1,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum SelfTest {
+    Off,
+    Xyz,
+}
+
+field_value!(SelfTest {
+    SelfTest::Off => 0,
+    SelfTest::Xyz => 7,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum I2cMasterClock {
+    Khz400,
+}
+
+field_value!(I2cMasterClock {
+    I2cMasterClock::Khz400 => 13,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum IcmBank {
+    Bank0,
+    Bank2,
+}
+
+field_value!(IcmBank {
+    IcmBank::Bank0 => 0,
+    IcmBank::Bank2 => 2,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HmcAveraging {
+    Samples1,
+    Samples2,
+    Samples4,
+    Samples8,
+}
+
+field_value!(HmcAveraging {
+    HmcAveraging::Samples1 => 0,
+    HmcAveraging::Samples2 => 1,
+    HmcAveraging::Samples4 => 2,
+    HmcAveraging::Samples8 => 3,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HmcBias {
+    Normal,
+    Positive,
+    Negative,
+}
+
+field_value!(HmcBias {
+    HmcBias::Normal => 0,
+    HmcBias::Positive => 1,
+    HmcBias::Negative => 2,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HmcGain {
+    /// 1090 LSB/Ga
+    Ga1_3,
+    /// 390 LSB/Ga, used by the self test
+    Ga4_7,
+}
+
+field_value!(HmcGain {
+    HmcGain::Ga1_3 => 1,
+    HmcGain::Ga4_7 => 5,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum HmcMode {
+    Continuous,
+    Single,
+}
+
+field_value!(HmcMode {
+    HmcMode::Continuous => 0,
+    HmcMode::Single => 1,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum QmcOversampling {
+    Samples512,
+}
+
+field_value!(QmcOversampling {
+    QmcOversampling::Samples512 => 0,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum QmcRange {
+    Ga8,
+}
+
+field_value!(QmcRange {
+    QmcRange::Ga8 => 1,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum QmcRate {
+    Hz10,
+    Hz50,
+    Hz100,
+}
+
+field_value!(QmcRate {
+    QmcRate::Hz10 => 0,
+    QmcRate::Hz50 => 1,
+    QmcRate::Hz100 => 2,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum QmcMode {
+    Continuous,
+}
+
+field_value!(QmcMode {
+    QmcMode::Continuous => 1,
+});
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Ak8963Mode {
+    PowerDown,
+    /// Continuous measurement at 8 Hz
+    Continuous8,
+    /// Continuous measurement at 100 Hz
+    Continuous100,
+    /// Exposes the factory sensitivity adjustment
+    FuseRom,
+}
+
+field_value!(Ak8963Mode {
+    Ak8963Mode::PowerDown => 0x00,
+    Ak8963Mode::Continuous8 => 0x02,
+    Ak8963Mode::Continuous100 => 0x06,
+    Ak8963Mode::FuseRom => 0x0F,
+});
+
+/// Continuous measurement rates of the AK09916
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Ak09916Mode {
+    Continuous10,
+    Continuous20,
+    Continuous50,
+    Continuous100,
+}
+
+field_value!(Ak09916Mode {
+    Ak09916Mode::Continuous10 => 0x02,
+    Ak09916Mode::Continuous20 => 0x04,
+    Ak09916Mode::Continuous50 => 0x06,
+    Ak09916Mode::Continuous100 => 0x08,
+});
+
+field_value!(BaroOversampling {
+    BaroOversampling::X1 => 0,
+    BaroOversampling::X2 => 1,
+    BaroOversampling::X4 => 2,
+    BaroOversampling::X8 => 3,
+});
+
+// measurement control including the start of conversion bit
+field_value!(Conversion {
+    Conversion::Temperature => 0x2E,
+    Conversion::Pressure => 0x34,
+});
+
+field_value!(GyroRange {
+    GyroRange::Dps250 => 0,
+    GyroRange::Dps500 => 1,
+    GyroRange::Dps1000 => 2,
+    GyroRange::Dps2000 => 3,
+});
+
+field_value!(AccelRange {
+    AccelRange::G2 => 0,
+    AccelRange::G4 => 1,
+    AccelRange::G8 => 2,
+    AccelRange::G16 => 3,
+});
+
+// the ICM-20948 uses the same codes with slightly different cut off frequencies
+field_value!(DlpfBandwidth {
+    DlpfBandwidth::Hz260 => 0,
+    DlpfBandwidth::Hz184 => 1,
+    DlpfBandwidth::Hz94 => 2,
+    DlpfBandwidth::Hz44 => 3,
+    DlpfBandwidth::Hz21 => 4,
+    DlpfBandwidth::Hz10 => 5,
+    DlpfBandwidth::Hz5 => 6,
+});
+
+field_value!(MagRate {
+    MagRate::Hz0_75 => 0,
+    MagRate::Hz1_5 => 1,
+    MagRate::Hz3 => 2,
+    MagRate::Hz7_5 => 3,
+    MagRate::Hz15 => 4,
+    MagRate::Hz30 => 5,
+    MagRate::Hz75 => 6,
+});
+
+// MPU6050, also used on the MPU6500 and MPU9250
+register!(Config, CONFIG);
+register!(GyroConfig, GYRO_CONFIG);
+register!(AccelConfig, ACCEL_CONFIG);
+register!(AccelConfig2, ACCEL_CONFIG_2);
+register!(FifoEnable, FIFO_ENABLE);
+register!(I2cMasterControl, I2C_MASTER_CONTROL);
+register!(I2cSlave0Addr, I2C_SLAVE0_ADDR);
+register!(I2cSlave0Control, I2C_SLAVE0_CONTROL);
+register!(IntPinConfig, INT_PIN_CONFIG);
+register!(IntEnable, INT_ENABLE);
+register!(IntStatus, INT_STATUS);
+register!(UserControl, USER_CONTROL);
+register!(PowerMgmt1, POWER_MGMT_1);
+
+pub const DLPF: Field<Config, DlpfBandwidth> = Field::new(2, 0);
+pub const GYRO_SELF_TEST: Field<GyroConfig, SelfTest> = Field::new(7, 5);
+pub const GYRO_FULL_SCALE: Field<GyroConfig, GyroRange> = Field::new(4, 3);
+pub const ACCEL_SELF_TEST: Field<AccelConfig, SelfTest> = Field::new(7, 5);
+pub const ACCEL_FULL_SCALE: Field<AccelConfig, AccelRange> = Field::new(4, 3);
+/// MPU6500 and MPU9250 only
+pub const ACCEL_DLPF: Field<AccelConfig2, DlpfBandwidth> = Field::new(2, 0);
+pub const FIFO_TEMP: Field<FifoEnable, bool> = Field::new(7, 7);
+pub const FIFO_GYRO_X: Field<FifoEnable, bool> = Field::new(6, 6);
+pub const FIFO_GYRO_Y: Field<FifoEnable, bool> = Field::new(5, 5);
+pub const FIFO_GYRO_Z: Field<FifoEnable, bool> = Field::new(4, 4);
+pub const FIFO_ACCEL: Field<FifoEnable, bool> = Field::new(3, 3);
+pub const FIFO_SLAVE0: Field<FifoEnable, bool> = Field::new(0, 0);
+pub const I2C_MASTER_CLOCK: Field<I2cMasterControl, I2cMasterClock> = Field::new(3, 0);
+pub const I2C_SLAVE0_READ: Field<I2cSlave0Addr, bool> = Field::new(7, 7);
+pub const I2C_SLAVE0_DEVICE: Field<I2cSlave0Addr, Bits<7>> = Field::new(6, 0);
+pub const I2C_SLAVE0_ENABLE: Field<I2cSlave0Control, bool> = Field::new(7, 7);
+pub const I2C_SLAVE0_LENGTH: Field<I2cSlave0Control, Bits<4>> = Field::new(3, 0);
+pub const INT_LATCH: Field<IntPinConfig, bool> = Field::new(5, 5);
+pub const INT_READ_CLEAR: Field<IntPinConfig, bool> = Field::new(4, 4);
+pub const I2C_BYPASS: Field<IntPinConfig, bool> = Field::new(1, 1);
+pub const DMP_INT: Field<IntEnable, bool> = Field::new(1, 1);
+pub const DATA_READY_INT: Field<IntEnable, bool> = Field::new(0, 0);
+pub const FIFO_OVERFLOW: Field<IntStatus, bool> = Field::new(4, 4);
+pub const DMP_ON: Field<UserControl, bool> = Field::new(7, 7);
+pub const FIFO_ON: Field<UserControl, bool> = Field::new(6, 6);
+pub const I2C_MASTER_MODE: Field<UserControl, bool> = Field::new(5, 5);
+pub const DMP_RESET: Field<UserControl, bool> = Field::new(3, 3);
+pub const FIFO_RESET: Field<UserControl, bool> = Field::new(2, 2);
+pub const SLEEP: Field<PowerMgmt1, bool> = Field::new(6, 6);
+pub const CLOCK_SELECT: Field<PowerMgmt1, ClockSource> = Field::new(2, 0);
+
+// ICM-20948, bank 0
+register!(IcmBankSelect, ICM20948_BANK_SELECT);
+register!(IcmUserControl, ICM20948_USER_CONTROL);
+register!(IcmPowerMgmt1, ICM20948_POWER_MGMT_1);
+register!(IcmIntPinConfig, ICM20948_INT_PIN_CONFIG);
+register!(IcmIntEnable1, ICM20948_INT_ENABLE_1);
+
+pub const ICM_BANK: Field<IcmBankSelect, IcmBank> = Field::new(5, 4);
+pub const ICM_I2C_MASTER_MODE: Field<IcmUserControl, bool> = Field::new(5, 5);
+pub const ICM_RESET: Field<IcmPowerMgmt1, bool> = Field::new(7, 7);
+pub const ICM_CLOCK_SELECT: Field<IcmPowerMgmt1, ClockSource> = Field::new(2, 0);
+pub const ICM_INT_LATCH: Field<IcmIntPinConfig, bool> = Field::new(5, 5);
+pub const ICM_INT_READ_CLEAR: Field<IcmIntPinConfig, bool> = Field::new(4, 4);
+pub const ICM_I2C_BYPASS: Field<IcmIntPinConfig, bool> = Field::new(1, 1);
+pub const ICM_DATA_READY_INT: Field<IcmIntEnable1, bool> = Field::new(0, 0);
+
+// ICM-20948, bank 2
+register!(IcmGyroConfig1, ICM20948_GYRO_CONFIG_1);
+register!(IcmAccelConfig, ICM20948_ACCEL_CONFIG);
+
+pub const ICM_GYRO_DLPF: Field<IcmGyroConfig1, DlpfBandwidth> = Field::new(5, 3);
+pub const ICM_GYRO_FULL_SCALE: Field<IcmGyroConfig1, GyroRange> = Field::new(2, 1);
+pub const ICM_GYRO_DLPF_ENABLE: Field<IcmGyroConfig1, bool> = Field::new(0, 0);
+pub const ICM_ACCEL_DLPF: Field<IcmAccelConfig, DlpfBandwidth> = Field::new(5, 3);
+pub const ICM_ACCEL_FULL_SCALE: Field<IcmAccelConfig, AccelRange> = Field::new(2, 1);
+pub const ICM_ACCEL_DLPF_ENABLE: Field<IcmAccelConfig, bool> = Field::new(0, 0);
+
+// HMC5883L
+register!(HmcConfigA, HMC5883L_CONFIG_A);
+register!(HmcConfigB, HMC5883L_CONFIG_B);
+register!(HmcModeReg, HMC5883L_MODE_REG);
+
+pub const HMC_AVERAGING: Field<HmcConfigA, HmcAveraging> = Field::new(6, 5);
+pub const HMC_RATE: Field<HmcConfigA, MagRate> = Field::new(4, 2);
+pub const HMC_BIAS: Field<HmcConfigA, HmcBias> = Field::new(1, 0);
+pub const HMC_GAIN: Field<HmcConfigB, HmcGain> = Field::new(7, 5);
+pub const HMC_MODE: Field<HmcModeReg, HmcMode> = Field::new(1, 0);
+
+// QMC5883L
+register!(QmcControl1, QMC5883L_CONTROL_1);
+register!(QmcControl2, QMC5883L_CONTROL_2);
+
+pub const QMC_OVERSAMPLING: Field<QmcControl1, QmcOversampling> = Field::new(7, 6);
+pub const QMC_RANGE: Field<QmcControl1, QmcRange> = Field::new(5, 4);
+pub const QMC_RATE: Field<QmcControl1, QmcRate> = Field::new(3, 2);
+pub const QMC_MODE: Field<QmcControl1, QmcMode> = Field::new(1, 0);
+pub const QMC_SOFT_RESET: Field<QmcControl2, bool> = Field::new(7, 7);
+
+// AK8963
+register!(Ak8963Control1, AK8963_CONTROL_1);
+register!(Ak8963Control2, AK8963_CONTROL_2);
+
+pub const AK8963_OUTPUT_16BIT: Field<Ak8963Control1, bool> = Field::new(4, 4);
+pub const AK8963_MODE: Field<Ak8963Control1, Ak8963Mode> = Field::new(3, 0);
+pub const AK8963_SOFT_RESET: Field<Ak8963Control2, bool> = Field::new(0, 0);
+
+// AK09916
+register!(Ak09916Control2, AK09916_CONTROL_2);
+register!(Ak09916Control3, AK09916_CONTROL_3);
+
+pub const AK09916_MODE: Field<Ak09916Control2, Ak09916Mode> = Field::new(4, 0);
+pub const AK09916_SOFT_RESET: Field<Ak09916Control3, bool> = Field::new(0, 0);
+
+// BMP180
+register!(Bmp180Control, BMP180_CONTROL);
+
+pub const BMP180_OVERSAMPLING: Field<Bmp180Control, BaroOversampling> = Field::new(7, 6);
+pub const BMP180_MEASUREMENT: Field<Bmp180Control, Conversion> = Field::new(5, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trips<V: FieldValue + PartialEq + core::fmt::Debug>(values: &[V]) {
+        for value in values {
+            assert!(value.bits() <= V::MAX);
+            assert_eq!(V::from_bits(value.bits()), Some(*value));
+        }
+    }
+
+    #[test]
+    fn mask_covers_the_field() {
+        assert_eq!(GYRO_FULL_SCALE.mask(), 0x18);
+        assert_eq!(CLOCK_SELECT.mask(), 0x07);
+        assert_eq!(DMP_ON.mask(), 0x80);
+        assert_eq!(FIFO_SLAVE0.mask(), 0x01);
+        assert_eq!(Field::<Config, Bits<8>>::new(7, 0).mask(), 0xFF);
+    }
+
+    #[test]
+    fn apply_only_touches_the_field() {
+        assert_eq!(GYRO_FULL_SCALE.apply(0x00, GyroRange::Dps1000), 0x10);
+        assert_eq!(GYRO_FULL_SCALE.apply(0xE7, GyroRange::Dps2000), 0xFF);
+        assert_eq!(GYRO_FULL_SCALE.apply(0xFF, GyroRange::Dps250), 0xE7);
+        assert_eq!(SLEEP.apply(0x41, false), 0x01);
+        assert_eq!(I2C_SLAVE0_DEVICE.apply(0x80, Bits::new(HMC5883L_ADDR).unwrap()), 0x80 | HMC5883L_ADDR);
+    }
+
+    #[test]
+    fn extract_reads_the_field() {
+        assert_eq!(GYRO_FULL_SCALE.extract(0xF7), Some(GyroRange::Dps1000));
+        assert_eq!(HMC_RATE.extract(0x78), Some(MagRate::Hz75));
+        assert_eq!(FIFO_OVERFLOW.extract(0x10), Some(true));
+        assert_eq!(FIFO_OVERFLOW.extract(0xEF), Some(false));
+        // gain code 2 isn't one of the HmcGain variants
+        assert_eq!(HMC_GAIN.extract(0x40), None);
+    }
+
+    #[test]
+    fn register_value_combines_fields() {
+        let value = RegisterValue::new()
+            .with(HMC_AVERAGING, HmcAveraging::Samples8)
+            .with(HMC_RATE, MagRate::Hz75)
+            .with(HMC_BIAS, HmcBias::Negative);
+        assert_eq!(value.bits(), 0x7A);
+
+        // a field set twice keeps the last value
+        let value = value.with(HMC_BIAS, HmcBias::Positive);
+        assert_eq!(value.bits(), 0x79);
+
+        assert_eq!(RegisterValue::<UserControl>::new().bits(), 0x00);
+
+        // 512x oversampling, 8 gauss, 100 Hz, continuous
+        let value = RegisterValue::new()
+            .with(QMC_OVERSAMPLING, QmcOversampling::Samples512)
+            .with(QMC_RANGE, QmcRange::Ga8)
+            .with(QMC_RATE, QmcRate::Hz100)
+            .with(QMC_MODE, QmcMode::Continuous);
+        assert_eq!(value.bits(), 0x19);
+
+        let value = RegisterValue::new().with(AK8963_OUTPUT_16BIT, true).with(AK8963_MODE, Ak8963Mode::Continuous100);
+        assert_eq!(value.bits(), 0x16);
+
+        let value = RegisterValue::new().with(BMP180_MEASUREMENT, Conversion::Pressure).with(BMP180_OVERSAMPLING, BaroOversampling::X8);
+        assert_eq!(value.bits(), 0xF4);
+    }
+
+    #[test]
+    fn bits_checks_its_width() {
+        assert_eq!(Bits::<4>::new(15).map(FieldValue::bits), Some(15));
+        assert_eq!(Bits::<4>::new(16), None);
+        assert_eq!(Bits::<7>::new(0x7F).map(FieldValue::bits), Some(0x7F));
+        assert_eq!(Bits::<7>::new(0x80), None);
+        assert_eq!(<Bits<7> as FieldValue>::MAX, 0x7F);
+        assert_eq!(<Bits<8> as FieldValue>::MAX, 0xFF);
+    }
+
+    #[test]
+    fn field_values_round_trip() {
+        round_trips(&[false, true]);
+        round_trips(&[ClockSource::Internal, ClockSource::Pll]);
+        round_trips(&[SelfTest::Off, SelfTest::Xyz]);
+        round_trips(&[I2cMasterClock::Khz400]);
+        round_trips(&[IcmBank::Bank0, IcmBank::Bank2]);
+        round_trips(&[HmcAveraging::Samples1, HmcAveraging::Samples2, HmcAveraging::Samples4, HmcAveraging::Samples8]);
+        round_trips(&[HmcBias::Normal, HmcBias::Positive, HmcBias::Negative]);
+        round_trips(&[HmcGain::Ga1_3, HmcGain::Ga4_7]);
+        round_trips(&[HmcMode::Continuous, HmcMode::Single]);
+        round_trips(&[QmcRate::Hz10, QmcRate::Hz50, QmcRate::Hz100]);
+        round_trips(&[Ak8963Mode::PowerDown, Ak8963Mode::Continuous8, Ak8963Mode::Continuous100, Ak8963Mode::FuseRom]);
+        round_trips(&[Ak09916Mode::Continuous10, Ak09916Mode::Continuous20, Ak09916Mode::Continuous50, Ak09916Mode::Continuous100]);
+        round_trips(&[BaroOversampling::X1, BaroOversampling::X2, BaroOversampling::X4, BaroOversampling::X8]);
+        round_trips(&[Conversion::Temperature, Conversion::Pressure]);
+        round_trips(&[GyroRange::Dps250, GyroRange::Dps500, GyroRange::Dps1000, GyroRange::Dps2000]);
+        round_trips(&[AccelRange::G2, AccelRange::G4, AccelRange::G8, AccelRange::G16]);
+        round_trips(&[
+            DlpfBandwidth::Hz260,
+            DlpfBandwidth::Hz184,
+            DlpfBandwidth::Hz94,
+            DlpfBandwidth::Hz44,
+            DlpfBandwidth::Hz21,
+            DlpfBandwidth::Hz10,
+            DlpfBandwidth::Hz5,
+        ]);
+        round_trips(&[MagRate::Hz0_75, MagRate::Hz1_5, MagRate::Hz3, MagRate::Hz7_5, MagRate::Hz15, MagRate::Hz30, MagRate::Hz75]);
+    }
+
+    #[test]
+    fn field_value_max_is_the_largest_code() {
+        assert_eq!(<bool as FieldValue>::MAX, 1);
+        assert_eq!(SelfTest::MAX, 7);
+        assert_eq!(I2cMasterClock::MAX, 13);
+        assert_eq!(HmcGain::MAX, 5);
+        assert_eq!(HmcBias::MAX, 2);
+        assert_eq!(DlpfBandwidth::MAX, 6);
+    }
+}
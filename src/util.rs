@@ -1,7 +1,6 @@
 use core::fmt::Write;
 
 use heapless::String;
-use rtt_target::rprintln;
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
 pub struct StringFromBufError {}
@@ -12,18 +11,19 @@ pub fn string_from_buf_with_skip<const N: usize>(
 ) -> Result<String<N>, StringFromBufError> {
     let mut s = String::new();
     for i in buf.iter().skip(skip) {
-        s.write_char(*i as char).map_err(|e| {
-            rprintln!("{:?}", e);
-            StringFromBufError {}
-        })?;
+        s.write_char(*i as char).map_err(|_| StringFromBufError {})?;
     }
     Ok(s)
 }
 
-pub fn convert_accel(input: i16) -> f32 {
-    (input as f32 * 2.0) / 32768.0
+pub fn convert_accel(input: i16, full_scale: f32) -> f32 {
+    (input as f32 * full_scale) / 32768.0
 }
 
-pub fn convert_gyro(input: i16) -> f32 {
-    (input as f32 * 250.0) / 32768.0
-}
\ No newline at end of file
+pub fn convert_gyro(input: i16, full_scale: f32) -> f32 {
+    (input as f32 * full_scale) / 32768.0
+}
+
+pub fn convert_temp(input: i16, sensitivity: f32, offset: f32) -> f32 {
+    input as f32 / sensitivity + offset
+}
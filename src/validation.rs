@@ -0,0 +1,229 @@
+use embassy_time::{Duration, Instant};
+use fusion_rs::Vec3;
+
+use crate::gy87::{AccelGyro, Gy87Error};
+
+/// Time the exact same sample may come back before the sensor counts as frozen, long
+/// enough that polling faster than the output rate doesn't trigger it
+const FROZEN_TIME: Duration = Duration::from_millis(500);
+/// Accel magnitude in g outside of which gravity is implausible
+const GRAVITY_MIN: f32 = 0.5;
+const GRAVITY_MAX: f32 = 2.0;
+/// Gyro change in °/s between two samples that counts as a spike
+const GYRO_SPIKE: f32 = 300.0;
+/// Value every HMC5883L axis reads once its ADC over- or underflowed
+pub const HMC_OVERFLOW: i16 = -4096;
+
+/// Quality flags of the samples behind a `MovementData`
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone)]
+pub struct SampleQuality {
+    /// Accel magnitude far from 1g, fine during hard motion but suspicious otherwise
+    pub gravity_implausible: bool,
+    /// A single sample gyro spike was replaced by the previous reading
+    pub gyro_spike: bool,
+    /// The magnetometer overflowed and its reading was dropped
+    pub mag_overflow: bool,
+}
+
+/// Plausibility checks on every sample before it reaches the fusion filter
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Validator {
+    last: Option<AccelGyro>,
+    unchanged_since: Option<Instant>,
+    /// Last gyro reading that was passed on
+    last_gyro: Option<Vec3>,
+    held_spike: bool,
+    quality: SampleQuality,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            unchanged_since: None,
+            last_gyro: None,
+            held_spike: false,
+            quality: SampleQuality::default(),
+        }
+    }
+
+    /// Rejects frozen and garbage samples, flags implausible gravity and holds the gyro
+    /// through single sample spikes
+    pub fn check(&mut self, mut accel_gyro: AccelGyro, now: Instant) -> Result<AccelGyro, Gy87Error> {
+        // all axes reading the same value means the bus returned a repeated byte,
+        // e.g. all 0xFF from a sensor that doesn't answer
+        let accel = accel_gyro.accel;
+        let gyro = accel_gyro.gyro;
+        if accel.x == accel.y && accel.y == accel.z && gyro.x == gyro.y && gyro.y == gyro.z {
+            return Err(Gy87Error::InvalidSample);
+        }
+
+        // sensor noise changes at least one LSB between conversions
+        if self.last == Some(accel_gyro) {
+            let since = *self.unchanged_since.get_or_insert(now);
+            if now - since >= FROZEN_TIME {
+                return Err(Gy87Error::FrozenSample);
+            }
+        } else {
+            self.unchanged_since = None;
+        }
+        self.last = Some(accel_gyro);
+
+        let magnitude = accel.norm();
+        if !(GRAVITY_MIN..=GRAVITY_MAX).contains(&magnitude) {
+            self.quality.gravity_implausible = true;
+        }
+
+        // a jump that doesn't persist into the next sample is a spike, a second jump
+        // in a row is real motion and gets through
+        if let Some(last_gyro) = self.last_gyro {
+            let jump = (gyro - last_gyro).abs().max();
+            if jump > GYRO_SPIKE && !self.held_spike {
+                self.held_spike = true;
+                self.quality.gyro_spike = true;
+                accel_gyro.gyro = last_gyro;
+                return Ok(accel_gyro);
+            }
+        }
+        self.held_spike = false;
+        self.last_gyro = Some(gyro);
+
+        Ok(accel_gyro)
+    }
+
+    /// Returns whether the raw HMC5883L reading is usable, flags it otherwise
+    pub fn check_hmc(&mut self, raw: &[i16; 3]) -> bool {
+        if raw.contains(&HMC_OVERFLOW) {
+            self.quality.mag_overflow = true;
+            return false;
+        }
+        true
+    }
+
+    /// Flags collected since the last call
+    pub fn take_quality(&mut self) -> SampleQuality {
+        core::mem::take(&mut self.quality)
+    }
+
+    /// Forgets the history, e.g. after the sensors were re-initialised
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fusion_rs::nalgebra::Vector3;
+
+    use super::*;
+
+    /// Level and still, `temp` tells samples apart
+    fn still(temp: f32) -> AccelGyro {
+        AccelGyro {
+            accel: Vector3::new(0.01, -0.02, 1.0),
+            gyro: Vector3::new(0.1, -0.2, 0.3),
+            temp,
+        }
+    }
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn passes_plausible_samples() {
+        let mut validator = Validator::new();
+        assert_eq!(validator.check(still(20.0), at(0)), Ok(still(20.0)));
+        assert_eq!(validator.check(still(20.1), at(10)), Ok(still(20.1)));
+        assert_eq!(validator.take_quality(), SampleQuality::default());
+    }
+
+    #[test]
+    fn rejects_repeated_bytes() {
+        let mut validator = Validator::new();
+        // every register 0xFF
+        let garbage = AccelGyro {
+            accel: Vector3::repeat(-0.00006),
+            gyro: Vector3::repeat(-0.0076),
+            temp: 36.5,
+        };
+        assert_eq!(validator.check(garbage, at(0)), Err(Gy87Error::InvalidSample));
+    }
+
+    #[test]
+    fn frozen_needs_the_whole_window() {
+        let mut validator = Validator::new();
+        let sample = still(20.0);
+
+        // polled faster than the sensor converts, the same sample comes back for a while
+        assert!(validator.check(sample, at(0)).is_ok());
+        for ms in 1..=400 {
+            assert!(validator.check(sample, at(ms)).is_ok());
+        }
+        assert_eq!(validator.check(sample, at(500)), Ok(sample));
+        assert_eq!(validator.check(sample, at(501)), Err(Gy87Error::FrozenSample));
+
+        // a new value starts the window over
+        assert!(validator.check(still(20.1), at(510)).is_ok());
+        assert!(validator.check(still(20.1), at(511)).is_ok());
+        assert!(validator.check(still(20.1), at(1010)).is_ok());
+        assert_eq!(validator.check(still(20.1), at(1011)), Err(Gy87Error::FrozenSample));
+    }
+
+    #[test]
+    fn flags_implausible_gravity() {
+        let mut validator = Validator::new();
+        let mut sample = still(20.0);
+        sample.accel = Vector3::new(0.0, 0.1, 2.5);
+        assert_eq!(validator.check(sample, at(0)), Ok(sample));
+
+        let quality = validator.take_quality();
+        assert!(quality.gravity_implausible);
+        assert!(!quality.gyro_spike);
+        // taking the flags clears them
+        assert_eq!(validator.take_quality(), SampleQuality::default());
+
+        sample.accel = Vector3::new(0.0, 0.1, 0.3);
+        sample.temp = 20.1;
+        validator.check(sample, at(10)).unwrap();
+        assert!(validator.take_quality().gravity_implausible);
+    }
+
+    #[test]
+    fn holds_the_gyro_through_a_single_spike() {
+        let mut validator = Validator::new();
+        validator.check(still(20.0), at(0)).unwrap();
+
+        let mut spike = still(20.1);
+        spike.gyro = Vector3::new(400.0, 0.0, 0.0);
+        let held = validator.check(spike, at(10)).unwrap();
+        assert_eq!(held.gyro, still(20.0).gyro);
+        assert_eq!(held.accel, spike.accel);
+        assert!(validator.take_quality().gyro_spike);
+
+        // the same jump again is real motion
+        spike.temp = 20.2;
+        assert_eq!(validator.check(spike, at(20)), Ok(spike));
+        assert!(!validator.take_quality().gyro_spike);
+    }
+
+    #[test]
+    fn rejects_hmc_overflow() {
+        let mut validator = Validator::new();
+        assert!(validator.check_hmc(&[100, -200, 300]));
+        assert!(!validator.take_quality().mag_overflow);
+
+        assert!(!validator.check_hmc(&[100, HMC_OVERFLOW, 300]));
+        assert!(validator.take_quality().mag_overflow);
+    }
+
+    #[test]
+    fn reset_forgets_the_history() {
+        let mut validator = Validator::new();
+        let sample = still(20.0);
+        validator.check(sample, at(0)).unwrap();
+        validator.check(sample, at(1)).unwrap();
+        validator.reset();
+        assert_eq!(validator.check(sample, at(600)), Ok(sample));
+    }
+}
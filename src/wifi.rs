@@ -6,7 +6,7 @@ use embassy_time::{Duration, Timer};
 use heapless::String;
 use rtt_target::{rprint, rprintln};
 
-use crate::util::{self, StringFromBufError};
+use headtracker_rs::util::{self, StringFromBufError};
 
 type UartType<'a> = Uart<'a, USART1, DMA2_CH7, DMA2_CH5>;
 